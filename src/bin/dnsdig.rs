@@ -0,0 +1,281 @@
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::process;
+use std::time::{Duration, Instant};
+
+use dns::dns::dns_cache::DnsCache;
+use dns::dns::dns_lookup::{lookup, recursive_lookup_traced, QueryOptions, ResolverConfig};
+use dns::dns::dns_packet::DnsPacket;
+use dns::dns::query_type::QueryType;
+use dns::utils::log::{set_level, LogLevel};
+use dns::utils::types::Result;
+
+/// The server queried when none is given, which is our own
+const DEFAULT_SERVER: &str = "127.0.0.1:2053";
+
+/// How long to wait for a response
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const USAGE: &str =
+    "usage: dnsdig [@server[:port]] [-p port] name [type] [+norecurse] [+tcp] [+trace] [+short]";
+
+/// What to ask, who to ask and how to show the answer, from the command line
+#[derive(Debug)]
+struct Args {
+    server: SocketAddr,
+    name: String,
+    qtype: QueryType,
+    recurse: bool,
+    tcp: bool,
+    trace: bool,
+    short: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Args> {
+    let mut server: Option<SocketAddr> = None;
+    let mut port = None;
+    let mut positional = Vec::new();
+    let mut parsed = Args {
+        server: DEFAULT_SERVER.parse()?,
+        name: String::new(),
+        qtype: QueryType::A,
+        recurse: true,
+        tcp: false,
+        trace: false,
+        short: false,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(addr) = arg.strip_prefix('@') {
+            // A server given without a port is a regular DNS server on port 53
+            server = Some(match addr.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, 53),
+                Err(_) => addr
+                    .parse()
+                    .map_err(|_| format!("invalid server address {}", addr))?,
+            });
+        } else if arg == "-p" {
+            let value = args.next().ok_or("-p needs a port")?;
+            port = Some(
+                value
+                    .parse::<u16>()
+                    .map_err(|_| format!("invalid port {}", value))?,
+            );
+        } else if let Some(flag) = arg.strip_prefix('+') {
+            match flag {
+                "norecurse" | "norec" => parsed.recurse = false,
+                "recurse" | "rec" => parsed.recurse = true,
+                "tcp" | "vc" => parsed.tcp = true,
+                "trace" => parsed.trace = true,
+                "short" => parsed.short = true,
+                _ => return Err(format!("unknown flag +{}", flag).into()),
+            }
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+
+    if let Some(server) = server {
+        parsed.server = server;
+    }
+    if let Some(port) = port {
+        parsed.server.set_port(port);
+    }
+
+    match positional.as_slice() {
+        [name] => parsed.name = name.to_string(),
+        [name, qtype] => {
+            parsed.name = name.to_string();
+            parsed.qtype = qtype.parse()?;
+        }
+        _ => return Err(USAGE.into()),
+    }
+    parsed.name = parsed.name.trim_end_matches('.').to_string();
+
+    Ok(parsed)
+}
+
+fn print_short(packet: &DnsPacket) {
+    for record in &packet.answers {
+        println!("{}", record.rdata());
+    }
+}
+
+// Ask the server given on the command line, and show its response
+fn query(args: &Args) -> Result<()> {
+    let options = QueryOptions {
+        timeout: TIMEOUT,
+        recursion_desired: args.recurse,
+        tcp: args.tcp,
+    };
+
+    let started = Instant::now();
+    let response = lookup(&args.name, args.qtype, args.server, &options)?;
+    let elapsed = started.elapsed();
+
+    if args.short {
+        print_short(&response);
+        return Ok(());
+    }
+
+    println!(";; Got answer:");
+    println!("{}", response);
+    println!(";; Query time: {} msec", elapsed.as_millis());
+    println!(
+        ";; SERVER: {}#{}({})",
+        args.server.ip(),
+        args.server.port(),
+        if args.tcp { "TCP" } else { "UDP" }
+    );
+
+    Ok(())
+}
+
+// Resolve the name ourselves, starting from the root, and show every
+// response we get on the way
+fn trace(args: &Args) -> Result<()> {
+    let cache = DnsCache::new();
+    let config = ResolverConfig::default();
+    let short = args.short;
+
+    let mut last = Instant::now();
+    let mut show_hop = |server: SocketAddr, result: &Result<DnsPacket>| {
+        let elapsed = last.elapsed();
+        last = Instant::now();
+        if short {
+            return;
+        }
+
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                println!(
+                    ";; No response from {}#{} in {} ms: {}\n",
+                    server.ip(),
+                    server.port(),
+                    elapsed.as_millis(),
+                    e
+                );
+                return;
+            }
+        };
+
+        for record in response
+            .answers
+            .iter()
+            .chain(response.authorities.iter())
+            .chain(response.resources.iter())
+        {
+            println!("{}", record);
+        }
+        let question = response
+            .questions
+            .first()
+            .map(|question| format!("{} {}", question.question_type, question.name))
+            .unwrap_or_default();
+        println!(
+            ";; {:?} for {} from {}#{} in {} ms\n",
+            response.header.rescode,
+            question,
+            server.ip(),
+            server.port(),
+            elapsed.as_millis()
+        );
+    };
+
+    let response = recursive_lookup_traced(&args.name, args.qtype, &cache, &config, &mut show_hop)?;
+
+    if short {
+        print_short(&response);
+    } else {
+        println!(";; Final answer:");
+        println!("{}", response);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args = match parse_args(&args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+
+    // The resolver's own logging would get in the way of the output
+    set_level(LogLevel::Off);
+
+    if !args.short {
+        println!("\n; <<>> dnsdig <<>> {} {}", args.name, args.qtype);
+    }
+
+    let result = if args.trace {
+        trace(&args)
+    } else {
+        query(&args)
+    };
+    if let Err(e) = result {
+        eprintln!(";; {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse(&["www.example.com."]).unwrap();
+        assert_eq!(args.server, DEFAULT_SERVER.parse().unwrap());
+        assert_eq!(args.name, "www.example.com");
+        assert_eq!(args.qtype, QueryType::A);
+        assert!(args.recurse && !args.tcp && !args.trace && !args.short);
+
+        let args = parse(&["+norec", "example.com", "mx", "+tcp", "+short", "+trace"]).unwrap();
+        assert_eq!(args.qtype, QueryType::MX);
+        assert!(!args.recurse && args.tcp && args.trace && args.short);
+        assert_eq!(
+            parse(&["example.com", "TYPE65534"]).unwrap().qtype,
+            QueryType::UNKNOWN(65534)
+        );
+    }
+
+    #[test]
+    fn test_parse_args_server() {
+        // A bare address means port 53, and -p overrides any port
+        let args = parse(&["@192.0.2.1", "example.com"]).unwrap();
+        assert_eq!(args.server, "192.0.2.1:53".parse().unwrap());
+        let args = parse(&["@[2001:db8::1]:5353", "example.com"]).unwrap();
+        assert_eq!(args.server, "[2001:db8::1]:5353".parse().unwrap());
+        let args = parse(&["-p", "5300", "example.com", "@192.0.2.1:5353"]).unwrap();
+        assert_eq!(args.server, "192.0.2.1:5300".parse().unwrap());
+        let args = parse(&["example.com", "-p", "5300"]).unwrap();
+        assert_eq!(args.server.port(), 5300);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        for args in [
+            &[][..],
+            &["example.com", "A", "extra"],
+            &["example.com", "BOGUS"],
+            &["example.com", "+bogus"],
+            &["@nowhere", "example.com"],
+            &["example.com", "-p"],
+            &["example.com", "-p", "65536"],
+        ] {
+            assert!(parse(args).is_err(), "{:?}", args);
+        }
+        assert_eq!(parse(&[]).unwrap_err().to_string(), USAGE);
+    }
+}
@@ -1,19 +1,68 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
 use crate::utils::types::Result;
 
-pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
+/// The largest message DNS can express, bounded by the two byte length
+/// prefix used over TCP
+pub const MAX_PACKET_SIZE: usize = 65535;
+
+pub struct BytePacketBuffer<'a> {
+    // Only the bytes that have actually been written or received are kept in
+    // `buf`, so its length is the valid length of the packet. A borrowed
+    // buffer is copied the first time it is written to.
+    buf: Cow<'a, [u8]>,
     pub pos: usize,
+    // Writes fail once the buffer would grow past this many bytes
+    limit: usize,
+    // Compression table mapping every name suffix written so far to the
+    // offset it was written at, so that later names can point back to it
+    names: HashMap<String, u16>,
 }
 
-/// BytePacketBuffer provides a convinient method of manipulating the packets
+impl Default for BytePacketBuffer<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl BytePacketBuffer {
+/// BytePacketBuffer provides a convinient method of manipulating the packets
+impl<'a> BytePacketBuffer<'a> {
     ///This gives us a fresh new BytePacketBuffer for holding the packet contents
     /// and a field for keeping track of where we are in the buffer
-    pub fn new() -> BytePacketBuffer {
+    pub fn new() -> BytePacketBuffer<'a> {
+        BytePacketBuffer::with_capacity(512)
+    }
+
+    /// A buffer holding up to `size` bytes, for packets larger than the
+    /// classic 512 byte limit such as EDNS(0) payloads
+    pub fn with_capacity(size: usize) -> BytePacketBuffer<'a> {
+        BytePacketBuffer {
+            buf: Cow::Owned(Vec::with_capacity(size)),
+            pos: 0,
+            limit: size,
+            names: HashMap::new(),
+        }
+    }
+
+    /// A buffer that starts out small and grows as it is written to, up to
+    /// the largest size a DNS message can have
+    pub fn growable() -> BytePacketBuffer<'a> {
+        BytePacketBuffer {
+            buf: Cow::Owned(Vec::new()),
+            pos: 0,
+            limit: MAX_PACKET_SIZE,
+            names: HashMap::new(),
+        }
+    }
+
+    /// Wrap received data for parsing without copying it
+    pub fn from_slice(data: &'a [u8]) -> BytePacketBuffer<'a> {
         BytePacketBuffer {
-            buf: [0; 512],
+            buf: Cow::Borrowed(data),
             pos: 0,
+            limit: data.len().max(MAX_PACKET_SIZE),
+            names: HashMap::new(),
         }
     }
 
@@ -22,6 +71,31 @@ impl BytePacketBuffer {
         self.pos
     }
 
+    //number of valid bytes in the buffer
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    //the most bytes the buffer will hold
+    pub fn capacity(&self) -> usize {
+        self.limit
+    }
+
+    //change the most bytes the buffer will hold, e.g. once we know how large
+    //a response the client is able to receive
+    pub fn set_capacity(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    //all the valid bytes in the buffer
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
     //step the buffer position forward a certain number of position
     pub fn step(&mut self, steps: usize) -> Result<()> {
         self.pos += steps;
@@ -29,14 +103,24 @@ impl BytePacketBuffer {
     }
 
     //change the buffer position
-    fn seek(&mut self, pos: usize) -> Result<()> {
+    pub fn seek(&mut self, pos: usize) -> Result<()> {
         self.pos = pos;
         Ok(())
     }
 
+    //drop everything past `len`, e.g. to undo a partially written record
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.buf.len() {
+            self.buf.to_mut().truncate(len);
+        }
+        // Names that were cut off can no longer be pointed to
+        self.names.retain(|_, offset| (*offset as usize) < len);
+        self.pos = self.pos.min(len);
+    }
+
     // read a single byte and move the position forward
     pub fn read(&mut self) -> Result<u8> {
-        if self.pos >= 512 {
+        if self.pos >= self.buf.len() {
             return Err("End of buffer".into());
         }
         let res = self.buf[self.pos];
@@ -45,25 +129,25 @@ impl BytePacketBuffer {
     }
 
     /// Get a single byte, without changing the buffer position
-    fn get(&mut self, pos: usize) -> Result<u8> {
-        if pos >= 512 {
+    fn get(&self, pos: usize) -> Result<u8> {
+        if pos >= self.buf.len() {
             return Err("End of buffer".into());
         }
         Ok(self.buf[pos])
     }
 
     //get a range of bytes
-    pub fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8]> {
-        if start + len > 512 {
+    pub fn get_range(&self, start: usize, len: usize) -> Result<&[u8]> {
+        if start + len > self.buf.len() {
             return Err("End of buffer".into());
         }
-        Ok(&self.buf[start..start + len as usize])
+        Ok(&self.buf[start..start + len])
     }
 
     //read two bytes stepping two bytes forward
     pub fn read_u16(&mut self) -> Result<u16> {
         let res = (self.read()? as u16) << 8 | (self.read()? as u16);
-        Ok(res as u16)
+        Ok(res)
     }
 
     //read four bytes stepping four bytes forward
@@ -71,10 +155,17 @@ impl BytePacketBuffer {
         let res = (self.read()? as u32) << 24
             | (self.read()? as u32) << 16
             | (self.read()? as u32) << 8
-            | (self.read()? as u32) << 0;
+            | (self.read()? as u32);
         Ok(res)
     }
 
+    //read a number of raw bytes stepping forward past them
+    pub fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>> {
+        let bytes = self.get_range(self.pos, len)?.to_vec();
+        self.pos += len;
+        Ok(bytes)
+    }
+
     ///read q name
     ///
     /// Read a domain name by reading the length bytes and concatenating them with dots in between
@@ -155,10 +246,17 @@ impl BytePacketBuffer {
 
     // write a a helper function for writing a single byte and moving the position forward
     fn write(&mut self, byte: u8) -> Result<()> {
-        if self.pos >= 512 {
+        if self.pos >= self.limit {
             return Err("End of buffer".into());
         }
-        self.buf[self.pos] = byte;
+        let buf = self.buf.to_mut();
+        if self.pos < buf.len() {
+            buf[self.pos] = byte;
+        } else {
+            // Anything skipped over with `step` or `seek` reads as zero
+            buf.resize(self.pos, 0);
+            buf.push(byte);
+        }
         self.pos += 1;
         Ok(())
     }
@@ -180,30 +278,76 @@ impl BytePacketBuffer {
         self.write((byte >> 24) as u8)?;
         self.write((byte >> 16) as u8)?;
         self.write((byte >> 8) as u8)?;
-        self.write((byte >> 0) as u8)?;
+        self.write(byte as u8)?;
+        Ok(())
+    }
+
+    //write_bytes writes a slice of raw bytes
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        for byte in bytes {
+            self.write(*byte)?;
+        }
         Ok(())
     }
 
-    //write_qname write query names in labeled form
+    //write_qname write query names in labeled form, using a pointer to an
+    //earlier occurrence for the longest suffix that was already written
     pub fn write_qname(&mut self, q_name: &str) -> Result<()> {
-        // Split the name on dots
-        for label in q_name.split('.') {
-            let len = label.len();
-            if len > 0x3f {
-                return Err("Label is too long and exceeds 63 characters".into());
+        self.write_name(q_name, true)
+    }
+
+    //write_qname_uncompressed write a name in full, for the rdata of record
+    //types that RFC 3597 doesn't allow to be compressed
+    pub fn write_qname_uncompressed(&mut self, q_name: &str) -> Result<()> {
+        self.write_name(q_name, false)
+    }
+
+    fn write_name(&mut self, q_name: &str, compress: bool) -> Result<()> {
+        // Split the name on dots. Empty labels are skipped so that both the
+        // root name "" and names with a trailing dot are written correctly.
+        let labels: Vec<&str> = q_name
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .collect();
+
+        // Validate the whole name up front, so we never leave half a name behind
+        if labels.iter().any(|label| label.len() > 0x3f) {
+            return Err("Label is too long and exceeds 63 characters".into());
+        }
+        if labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1 > 255 {
+            return Err("Name is too long and exceeds 255 bytes".into());
+        }
+
+        for (i, label) in labels.iter().enumerate() {
+            if compress {
+                let suffix = labels[i..].join(".").to_lowercase();
+
+                // The rest of the name has already been written, so we can
+                // jump there instead of repeating it
+                if let Some(&offset) = self.names.get(&suffix) {
+                    self.write_u16(0xC000 | offset)?;
+                    return Ok(());
+                }
+
+                // Pointers only have 14 bits for the offset
+                if self.pos <= 0x3FFF {
+                    self.names.insert(suffix, self.pos as u16);
+                }
             }
-            self.write_u8(len as u8)?;
+
+            self.write_u8(label.len() as u8)?;
             // write the label
-            for byte in label.as_bytes() {
-                self.write(*byte)?;
-            }
+            self.write_bytes(label.as_bytes())?;
         }
         self.write_u8(0)?;
         Ok(())
     }
 
     fn set(&mut self, pos: usize, val: u8) -> Result<()> {
-        self.buf[pos] = val;
+        if pos >= self.buf.len() {
+            return Err("End of buffer".into());
+        }
+        self.buf.to_mut()[pos] = val;
 
         Ok(())
     }
@@ -219,7 +363,7 @@ impl BytePacketBuffer {
 #[cfg(test)]
 mod tests {
     use super::*;
-    fn create_byte_packet_buffer() -> BytePacketBuffer {
+    fn create_byte_packet_buffer() -> BytePacketBuffer<'static> {
         BytePacketBuffer::new()
     }
     #[test]
@@ -230,6 +374,7 @@ mod tests {
     #[test]
     fn test_get_range_from_buffer() {
         let mut buffer = create_byte_packet_buffer();
+        buffer.write_bytes(&[0; 10]).unwrap();
         let result = buffer.get_range(0, 10);
         assert!(result.is_ok());
         let right_val = result.unwrap();
@@ -237,6 +382,14 @@ mod tests {
         assert_eq!(right_val, vec![0; 10]);
     }
     #[test]
+    fn test_get_range_past_valid_length() {
+        let mut buffer = create_byte_packet_buffer();
+        buffer.write_u16(0x1234).unwrap();
+        assert!(buffer.get_range(0, 10).is_err());
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.capacity(), 512);
+    }
+    #[test]
     fn test_write_single_byte() {
         let mut buffer = create_byte_packet_buffer();
         buffer.write_u8(0x12).unwrap();
@@ -259,22 +412,25 @@ mod tests {
     fn test_read_single_byte() {
         let mut buffer = create_byte_packet_buffer();
         buffer.write_u8(0x12).unwrap();
+        buffer.seek(0).unwrap();
         let result = buffer.read();
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0x12);
     }
     #[test]
     fn test_read_two_bytes() {
         let mut buffer = create_byte_packet_buffer();
         buffer.write_u16(0x1234).unwrap();
+        buffer.seek(0).unwrap();
         let result = buffer.read_u16();
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0x1234);
     }
     #[test]
     fn test_read_four_bytes() {
         let mut buffer = create_byte_packet_buffer();
         buffer.write_u32(0x12345678).unwrap();
+        buffer.seek(0).unwrap();
         let result = buffer.read_u32();
-        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 0x12345678);
     }
     #[test]
     fn test_write_qname() {
@@ -283,10 +439,52 @@ mod tests {
         assert_eq!(buffer.pos(), 17);
     }
     #[test]
+    fn test_write_qname_compressed() {
+        let mut buffer = create_byte_packet_buffer();
+        buffer.write_qname("www.example.com").unwrap();
+        buffer.write_qname("mail.example.com").unwrap();
+        // [4]mail followed by a two byte pointer to example.com
+        assert_eq!(buffer.pos(), 17 + 7);
+
+        buffer.seek(17).unwrap();
+        let mut qname = String::new();
+        buffer.read_qname(&mut qname).unwrap();
+        assert_eq!(qname, "mail.example.com");
+    }
+    #[test]
+    fn test_write_qname_uncompressed() {
+        let mut buffer = create_byte_packet_buffer();
+        buffer.write_qname("www.example.com").unwrap();
+        buffer.write_qname_uncompressed("www.example.com").unwrap();
+        assert_eq!(buffer.pos(), 34);
+    }
+    #[test]
     fn test_read_qname() {
         let mut buffer = create_byte_packet_buffer();
         buffer.write_qname("www.example.com").unwrap();
-        let result = buffer.read_qname(&mut "www.example.com".to_owned());
+        buffer.seek(0).unwrap();
+        let mut qname = String::new();
+        let result = buffer.read_qname(&mut qname);
         assert!(result.is_ok());
+        assert_eq!(qname, "www.example.com");
+    }
+    #[test]
+    fn test_write_past_capacity() {
+        let mut buffer = BytePacketBuffer::with_capacity(2);
+        buffer.write_u16(0x1234).unwrap();
+        assert!(buffer.write_u8(0x56).is_err());
+    }
+    #[test]
+    fn test_growable_buffer() {
+        let mut buffer = BytePacketBuffer::growable();
+        buffer.write_bytes(&[0xab; 1000]).unwrap();
+        assert_eq!(buffer.len(), 1000);
+    }
+    #[test]
+    fn test_from_slice() {
+        let data = [0x12, 0x34];
+        let mut buffer = BytePacketBuffer::from_slice(&data);
+        assert_eq!(buffer.read_u16().unwrap(), 0x1234);
+        assert!(buffer.read().is_err());
     }
 }
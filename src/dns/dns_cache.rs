@@ -0,0 +1,585 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use super::dns_header::ResultCode;
+use super::dns_packet::DnsPacket;
+use super::dns_record::DnsRecord;
+use super::query_type::QueryType;
+
+/// How often `store` sweeps the whole cache for expired entries
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Upper bound on the number of CNAMEs followed when answering from the cache
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// RFC 2308 recommends not holding on to negative answers for more than a
+/// few hours, whatever the SOA says
+const MAX_NEGATIVE_TTL: u32 = 3 * 60 * 60;
+
+/// How many entries the cache holds unless told otherwise
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// The two flavours of negative answer described in RFC 2308
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NegativeKind {
+    /// The name doesn't exist at all, for any type
+    NxDomain,
+    /// The name exists, but has no records of the requested type
+    NoData,
+}
+
+/// A set of records sharing the same name and type, along with the moment
+/// they were stored so that the remaining TTL can be computed on the way out
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    records: Vec<DnsRecord>,
+    stored_at: Instant,
+    expires_at: Instant,
+}
+
+impl CacheEntry {
+    fn new(records: Vec<DnsRecord>, now: Instant) -> CacheEntry {
+        // An RRset lives as long as its shortest lived member
+        let ttl = records.iter().map(|rec| rec.ttl()).min().unwrap_or(0);
+
+        CacheEntry {
+            records,
+            stored_at: now,
+            expires_at: now + Duration::from_secs(ttl as u64),
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Hand out copies of the records with their TTL reduced by the time
+    /// they have spent in the cache
+    fn records(&self, now: Instant) -> Vec<DnsRecord> {
+        let elapsed = now.duration_since(self.stored_at).as_secs();
+        let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+
+        self.records
+            .iter()
+            .cloned()
+            .map(|mut rec| {
+                rec.set_ttl(rec.ttl().saturating_sub(elapsed));
+                rec
+            })
+            .collect()
+    }
+}
+
+struct CacheState {
+    entries: HashMap<(String, QueryType), CacheEntry>,
+    // Negative entries hold the SOA record from the authority section, which
+    // is handed back to clients along with the negative answer
+    nxdomain: HashMap<String, CacheEntry>,
+    nodata: HashMap<(String, QueryType), CacheEntry>,
+    last_sweep: Instant,
+}
+
+impl CacheState {
+    fn len(&self) -> usize {
+        self.entries.len() + self.nxdomain.len() + self.nodata.len()
+    }
+
+    // Keep only the entries that are still live at `instant`
+    fn retain_live(&mut self, instant: Instant) {
+        self.entries.retain(|_, entry| !entry.is_expired(instant));
+        self.nxdomain.retain(|_, entry| !entry.is_expired(instant));
+        self.nodata.retain(|_, entry| !entry.is_expired(instant));
+    }
+}
+
+/// DnsCache keeps the records we've learned from upstream servers so that
+/// repeated questions can be answered without going back to the network
+pub struct DnsCache {
+    state: RwLock<CacheState>,
+    max_entries: usize,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsCache {
+    pub fn new() -> DnsCache {
+        DnsCache::with_capacity(DEFAULT_MAX_ENTRIES)
+    }
+
+    /// Create a cache that holds at most `max_entries` positive and negative
+    /// entries
+    pub fn with_capacity(max_entries: usize) -> DnsCache {
+        DnsCache {
+            state: RwLock::new(CacheState {
+                entries: HashMap::new(),
+                nxdomain: HashMap::new(),
+                nodata: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+            max_entries,
+        }
+    }
+
+    fn key(qname: &str, qtype: QueryType) -> (String, QueryType) {
+        (qname.to_lowercase(), qtype)
+    }
+
+    /// Get the live records for an exact name and type, if any
+    pub fn get(&self, qname: &str, qtype: QueryType) -> Option<Vec<DnsRecord>> {
+        let now = Instant::now();
+        let state = self.state.read().ok()?;
+
+        match state.entries.get(&Self::key(qname, qtype)) {
+            Some(entry) if !entry.is_expired(now) => Some(entry.records(now)),
+            _ => None,
+        }
+    }
+
+    /// Get the SOA records of a live negative entry for a name and type, if any
+    pub fn get_negative(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+    ) -> Option<(NegativeKind, Vec<DnsRecord>)> {
+        let now = Instant::now();
+        let state = self.state.read().ok()?;
+        let key = Self::key(qname, qtype);
+
+        if let Some(entry) = state.nxdomain.get(&key.0) {
+            if !entry.is_expired(now) {
+                return Some((NegativeKind::NxDomain, entry.records(now)));
+            }
+        }
+
+        match state.nodata.get(&key) {
+            Some(entry) if !entry.is_expired(now) => {
+                Some((NegativeKind::NoData, entry.records(now)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Try to answer a question from the cache alone. When the name is an
+    /// alias, the CNAME chain is followed as far as the cache allows, and an
+    /// answer is only produced if it ends in records of the requested type
+    /// or in a cached negative answer.
+    pub fn lookup(&self, qname: &str, qtype: QueryType) -> Option<DnsPacket> {
+        let mut packet = DnsPacket::new();
+        let mut name = qname.to_string();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(records) = self.get(&name, qtype) {
+                packet.answers.extend(records);
+                return Some(packet);
+            }
+
+            // Per RFC 2308 a negative answer applies to the last name in the
+            // chain, so any CNAMEs collected so far are kept in the answer
+            if let Some((kind, soa)) = self.get_negative(&name, qtype) {
+                if kind == NegativeKind::NxDomain {
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                }
+                packet.authorities.extend(soa);
+                return Some(packet);
+            }
+
+            if qtype == QueryType::CNAME {
+                return None;
+            }
+
+            let cname = self.get(&name, QueryType::CNAME)?;
+            name = match cname.first() {
+                Some(DnsRecord::CNAME { host, .. }) => host.clone(),
+                _ => return None,
+            };
+            packet.answers.extend(cname);
+        }
+
+        None
+    }
+
+    /// Find the closest enclosing zone of `qname` that we have both NS and A
+    /// or AAAA records for, along with the addresses of its name servers, so
+    /// that resolution can skip the parts of the hierarchy we already know
+    pub fn lookup_closest_ns(&self, qname: &str) -> Option<(String, Vec<IpAddr>)> {
+        let mut zone = qname;
+
+        loop {
+            if let Some(records) = self.get(zone, QueryType::NS) {
+                let addrs: Vec<IpAddr> = records
+                    .iter()
+                    .filter_map(|rec| match rec {
+                        DnsRecord::NS { host, .. } => Some(host),
+                        _ => None,
+                    })
+                    .flat_map(|host| {
+                        let v4 = self.get(host, QueryType::A).unwrap_or_default();
+                        let v6 = self.get(host, QueryType::AAAA).unwrap_or_default();
+                        v4.into_iter().chain(v6)
+                    })
+                    .filter_map(|rec| match rec {
+                        DnsRecord::A { addr, .. } => Some(IpAddr::V4(addr)),
+                        DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(addr)),
+                        _ => None,
+                    })
+                    .collect();
+
+                if !addrs.is_empty() {
+                    return Some((zone.to_lowercase(), addrs));
+                }
+            }
+
+            if zone.is_empty() {
+                return None;
+            }
+
+            // Move up one label, ending with the root zone
+            zone = match zone.find('.') {
+                Some(idx) => &zone[idx + 1..],
+                None => "",
+            };
+        }
+    }
+
+    /// Store every record of a response, grouped into sets by name and type.
+    /// A fresh set replaces whatever was cached for the same name and type.
+    pub fn store(&self, records: &[DnsRecord]) {
+        let now = Instant::now();
+
+        let mut sets: HashMap<(String, QueryType), Vec<DnsRecord>> = HashMap::new();
+        for rec in records {
+            if rec.ttl() == 0 {
+                continue;
+            }
+            sets.entry(Self::key(rec.domain(), rec.query_type()))
+                .or_default()
+                .push(rec.clone());
+        }
+
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        for (key, records) in sets {
+            // Fresh data for a name overrides anything negative we knew
+            state.nxdomain.remove(&key.0);
+            state.nodata.remove(&key);
+            state.entries.insert(key, CacheEntry::new(records, now));
+        }
+
+        Self::sweep(&mut state, now);
+        Self::evict(&mut state, now, self.max_entries);
+    }
+
+    /// Remember a negative response for `qname` and `qtype`. When the answer
+    /// section holds a CNAME chain, the negative answer is about the last name
+    /// of the chain rather than the aliases leading to it (RFC 2308 section
+    /// 2), so that is the name it is stored under. Responses that aren't
+    /// negative, or that lack the SOA record needed to know how long the
+    /// answer may be cached, are ignored.
+    pub fn store_negative(&self, qname: &str, qtype: QueryType, response: &DnsPacket) {
+        let mut name = qname.to_string();
+        if qtype != QueryType::CNAME {
+            for _ in 0..MAX_CNAME_CHAIN {
+                let target = response.answers.iter().find_map(|rec| match rec {
+                    DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(&name) => {
+                        Some(host.clone())
+                    }
+                    _ => None,
+                });
+                match target {
+                    Some(target) => name = target,
+                    None => break,
+                }
+            }
+        }
+
+        let answered = response
+            .answers
+            .iter()
+            .any(|rec| rec.query_type() == qtype && rec.domain().eq_ignore_ascii_case(&name));
+        let kind = match response.header.rescode {
+            ResultCode::NXDOMAIN => NegativeKind::NxDomain,
+            ResultCode::NOERROR if !answered => NegativeKind::NoData,
+            _ => return,
+        };
+
+        let mut soa = match response.get_soa() {
+            Some(soa) => soa.clone(),
+            None => return,
+        };
+
+        let ttl = response
+            .get_negative_ttl()
+            .unwrap_or(0)
+            .min(MAX_NEGATIVE_TTL);
+        if ttl == 0 {
+            return;
+        }
+        soa.set_ttl(ttl);
+
+        let now = Instant::now();
+        let mut state = match self.state.write() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+
+        let key = Self::key(&name, qtype);
+        let entry = CacheEntry::new(vec![soa], now);
+        match kind {
+            NegativeKind::NxDomain => {
+                state.nxdomain.insert(key.0, entry);
+            }
+            NegativeKind::NoData => {
+                state.nodata.insert(key, entry);
+            }
+        }
+
+        Self::sweep(&mut state, now);
+        Self::evict(&mut state, now, self.max_entries);
+    }
+
+    /// Drop expired entries every once in a while so the maps don't grow
+    /// without bound
+    fn sweep(state: &mut CacheState, now: Instant) {
+        if now.duration_since(state.last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+
+        state.retain_live(now);
+        state.last_sweep = now;
+    }
+
+    /// Make room when the cache holds more than `max_entries`. Expired
+    /// entries go first, then those closest to expiring, which are the least
+    /// useful to keep. Some extra room is made so that this doesn't have to
+    /// happen again on the very next store.
+    fn evict(state: &mut CacheState, now: Instant, max_entries: usize) {
+        if state.len() <= max_entries {
+            return;
+        }
+
+        state.retain_live(now);
+        state.last_sweep = now;
+        if state.len() <= max_entries {
+            return;
+        }
+
+        let target = max_entries - max_entries / 10;
+        let mut expiries: Vec<Instant> = state
+            .entries
+            .values()
+            .chain(state.nxdomain.values())
+            .chain(state.nodata.values())
+            .map(|entry| entry.expires_at)
+            .collect();
+        let excess = expiries.len() - target;
+        let (_, cutoff, _) = expiries.select_nth_unstable(excess - 1);
+        let cutoff = *cutoff;
+
+        // Entries stored together expire together, so of those expiring right
+        // at the cutoff only as many go as are needed to reach the target
+        let mut ties = excess - expiries.iter().filter(|&&at| at < cutoff).count();
+        let mut keep = |entry: &CacheEntry| {
+            if entry.expires_at == cutoff && ties > 0 {
+                ties -= 1;
+                return false;
+            }
+            entry.expires_at >= cutoff
+        };
+        state.entries.retain(|_, entry| keep(entry));
+        state.nxdomain.retain(|_, entry| keep(entry));
+        state.nodata.retain(|_, entry| keep(entry));
+    }
+
+    /// Number of positive and negative entries currently held, including expired ones that
+    /// haven't been swept yet
+    pub fn len(&self) -> usize {
+        self.state.read().map(|state| state.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn a_record(domain: &str, addr: Ipv4Addr, ttl: u32) -> DnsRecord {
+        DnsRecord::A {
+            domain: domain.to_string(),
+            addr,
+            ttl,
+        }
+    }
+
+    #[test]
+    fn test_store_and_lookup() {
+        let cache = DnsCache::new();
+        cache.store(&[a_record("www.example.com", Ipv4Addr::new(1, 2, 3, 4), 300)]);
+
+        let packet = cache.lookup("www.example.com", QueryType::A).unwrap();
+        assert_eq!(packet.answers.len(), 1);
+        assert!(packet.answers[0].ttl() <= 300);
+        assert!(cache.lookup("www.example.com", QueryType::AAAA).is_none());
+    }
+
+    #[test]
+    fn test_zero_ttl_is_not_cached() {
+        let cache = DnsCache::new();
+        cache.store(&[a_record("www.example.com", Ipv4Addr::new(1, 2, 3, 4), 0)]);
+
+        assert!(cache.is_empty());
+        assert!(cache.lookup("www.example.com", QueryType::A).is_none());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let cache = DnsCache::with_capacity(2);
+        cache.store(&[a_record("a.example.com", Ipv4Addr::new(1, 2, 3, 4), 100)]);
+        cache.store(&[a_record("b.example.com", Ipv4Addr::new(1, 2, 3, 4), 300)]);
+        cache.store(&[a_record("c.example.com", Ipv4Addr::new(1, 2, 3, 4), 200)]);
+
+        // The entry closest to expiring made room for the others
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a.example.com", QueryType::A).is_none());
+        assert!(cache.get("b.example.com", QueryType::A).is_some());
+    }
+
+    #[test]
+    fn test_capacity_with_equal_expiry() {
+        let now = Instant::now();
+        let mut state = CacheState {
+            entries: HashMap::new(),
+            nxdomain: HashMap::new(),
+            nodata: HashMap::new(),
+            last_sweep: now,
+        };
+        for i in 0..20 {
+            let domain = format!("{}.example.com", i);
+            let record = a_record(&domain, Ipv4Addr::new(1, 2, 3, 4), 300);
+            state
+                .entries
+                .insert((domain, QueryType::A), CacheEntry::new(vec![record], now));
+        }
+
+        // Entries that expire at the same moment don't all go at once
+        DnsCache::evict(&mut state, now, 10);
+        assert_eq!(state.len(), 9);
+    }
+
+    #[test]
+    fn test_lookup_follows_cname() {
+        let cache = DnsCache::new();
+        cache.store(&[
+            DnsRecord::CNAME {
+                domain: "www.example.com".to_string(),
+                host: "example.com".to_string(),
+                ttl: 300,
+            },
+            a_record("example.com", Ipv4Addr::new(1, 2, 3, 4), 300),
+        ]);
+
+        let packet = cache.lookup("www.example.com", QueryType::A).unwrap();
+        assert_eq!(packet.answers.len(), 2);
+    }
+
+    #[test]
+    fn test_lookup_closest_ns() {
+        let cache = DnsCache::new();
+        cache.store(&[
+            DnsRecord::NS {
+                domain: "example.com".to_string(),
+                host: "ns1.example.com".to_string(),
+                ttl: 300,
+            },
+            a_record("ns1.example.com", Ipv4Addr::new(10, 0, 0, 1), 300),
+        ]);
+
+        assert_eq!(
+            cache.lookup_closest_ns("www.example.com"),
+            Some((
+                "example.com".to_string(),
+                vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]
+            ))
+        );
+        assert!(cache.lookup_closest_ns("www.example.org").is_none());
+    }
+
+    fn negative_response(rescode: ResultCode, soa_ttl: u32) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.header.rescode = rescode;
+        packet.authorities.push(DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+            ttl: soa_ttl,
+        });
+        packet
+    }
+
+    #[test]
+    fn test_nxdomain_applies_to_all_types() {
+        let cache = DnsCache::new();
+        let response = negative_response(ResultCode::NXDOMAIN, 300);
+        cache.store_negative("nope.example.com", QueryType::A, &response);
+
+        let packet = cache.lookup("nope.example.com", QueryType::MX).unwrap();
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(packet.authorities.len(), 1);
+    }
+
+    #[test]
+    fn test_nxdomain_behind_cname() {
+        let cache = DnsCache::new();
+        let mut response = negative_response(ResultCode::NXDOMAIN, 300);
+        response.answers.push(DnsRecord::CNAME {
+            domain: "www.example.com".to_string(),
+            host: "gone.example.com".to_string(),
+            ttl: 300,
+        });
+        cache.store(&response.answers);
+        cache.store_negative("www.example.com", QueryType::A, &response);
+
+        // The alias exists, it's its target that doesn't
+        assert!(cache
+            .get_negative("www.example.com", QueryType::A)
+            .is_none());
+        assert!(cache
+            .get_negative("gone.example.com", QueryType::A)
+            .is_some());
+
+        let packet = cache.lookup("www.example.com", QueryType::A).unwrap();
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert_eq!(packet.answers.len(), 1);
+        assert_eq!(packet.authorities.len(), 1);
+    }
+
+    #[test]
+    fn test_nodata_applies_to_single_type() {
+        let cache = DnsCache::new();
+        let response = negative_response(ResultCode::NOERROR, 300);
+        cache.store_negative("www.example.com", QueryType::AAAA, &response);
+
+        let packet = cache.lookup("www.example.com", QueryType::AAAA).unwrap();
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities.len(), 1);
+        assert!(cache.lookup("www.example.com", QueryType::A).is_none());
+    }
+}
@@ -20,7 +20,7 @@ impl ResultCode {
             3 => ResultCode::NXDOMAIN,
             4 => ResultCode::NOTIMP,
             5 => ResultCode::REFUSED,
-            0 | _ => ResultCode::NOERROR,
+            _ => ResultCode::NOERROR,
         }
     }
 }
@@ -47,6 +47,12 @@ pub struct DnsHeader {
     pub resource_entries: u16,      // 16 bits
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsHeader {
     pub fn new() -> DnsHeader {
         DnsHeader {
@@ -106,7 +112,7 @@ impl DnsHeader {
             (self.recursion_desired as u8)
                 | ((self.truncated_message as u8) << 1)
                 | ((self.authoritative_answer as u8) << 2)
-                | ((self.opcode as u8) << 3)
+                | (self.opcode << 3)
                 | ((self.response as u8) << 7),
         )?;
         // write rescode
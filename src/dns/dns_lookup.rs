@@ -1,58 +1,683 @@
-use std::net::{Ipv4Addr, UdpSocket};
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
 
 use crate::buffer::buffer::BytePacketBuffer;
+use crate::log_debug;
+use crate::utils::random::{random_range, random_u16, shuffle};
+use crate::utils::tcp::{read_message, write_message};
 use crate::utils::types::Result;
 
+use super::dns_cache::DnsCache;
 use super::dns_header::ResultCode;
-use super::dns_packet::DnsPacket;
+use super::dns_packet::{DnsPacket, MAX_UDP_PAYLOAD_SIZE};
 use super::dns_question::DnsQuestion;
+use super::dns_record::DnsRecord;
+use super::domain_name::is_strict_subdomain;
 use super::query_type::QueryType;
+use super::root_hints::RootHints;
 
-// Add lookup method to lookup DNS records
-fn lookup(query_name: &str, query_type: QueryType, server: (Ipv4Addr, u16)) -> Result<DnsPacket> {
-    // bind a UDP socket to arbitrary port
-    let socket = UdpSocket::bind(("0.0.0.0", 42340))?;
+/// Which address families the resolver may use to reach upstream servers
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IpPolicy {
+    Ipv4Only,
+    Ipv6Only,
+    PreferIpv4,
+    PreferIpv6,
+}
+
+impl IpPolicy {
+    pub fn allows(&self, addr: &IpAddr) -> bool {
+        match *self {
+            IpPolicy::Ipv4Only => addr.is_ipv4(),
+            IpPolicy::Ipv6Only => addr.is_ipv6(),
+            IpPolicy::PreferIpv4 | IpPolicy::PreferIpv6 => true,
+        }
+    }
+
+    /// Drop the addresses we aren't allowed to use, and put the preferred
+    /// family first. Within a family the servers are put in a random order,
+    /// so that our queries are spread over the servers of a zone.
+    pub fn order(&self, servers: &mut Vec<IpAddr>) {
+        servers.retain(|addr| self.allows(addr));
+        shuffle(servers);
+
+        let prefer_ipv6 = *self == IpPolicy::PreferIpv6;
+        servers.sort_by_key(|addr| addr.is_ipv6() != prefer_ipv6);
+    }
+
+    /// The address record types to look up for a name server, in order of
+    /// preference
+    pub fn address_types(&self) -> Vec<QueryType> {
+        match *self {
+            IpPolicy::Ipv4Only => vec![QueryType::A],
+            IpPolicy::Ipv6Only => vec![QueryType::AAAA],
+            IpPolicy::PreferIpv4 => vec![QueryType::A, QueryType::AAAA],
+            IpPolicy::PreferIpv6 => vec![QueryType::AAAA, QueryType::A],
+        }
+    }
+}
+
+/// The port name servers answer queries on
+const DNS_PORT: u16 = 53;
+
+/// Upper bound on the number of CNAMEs followed to answer a single question
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// The most minimised queries sent while looking for the zone cuts of a name,
+/// after which the full name is sent (MAX_MINIMISE_COUNT in RFC 9156)
+const MAX_MINIMISE_COUNT: usize = 10;
+
+/// Knobs controlling how patient the resolver is with upstream servers
+#[derive(Clone, Debug)]
+pub struct ResolverConfig {
+    /// How long to wait for a single server to answer a single query
+    pub query_timeout: Duration,
+    /// How many more rounds over a zone's servers are made when none of them
+    /// answered
+    pub retries: usize,
+    /// The longest a whole resolution may take, nested lookups included
+    pub resolution_timeout: Duration,
+    /// Whether to talk to upstream servers over IPv4, IPv6 or both
+    pub ip_policy: IpPolicy,
+    /// The root servers to start from when nothing closer is known
+    pub root_hints: RootHints,
+    /// The most queries sent upstream to answer a single question
+    pub max_queries: usize,
+    /// How deeply lookups of name server addresses may nest
+    pub max_ns_depth: usize,
+    /// The most referrals followed in a single lookup
+    pub max_referrals: usize,
+    /// Whether to only reveal as much of a name to each server as it needs
+    /// to refer us further down the tree (RFC 9156)
+    pub qname_minimisation: bool,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            query_timeout: Duration::from_secs(2),
+            retries: 2,
+            resolution_timeout: Duration::from_secs(10),
+            ip_policy: IpPolicy::PreferIpv4,
+            root_hints: RootHints::default(),
+            max_queries: 100,
+            max_ns_depth: 6,
+            max_referrals: 16,
+            qname_minimisation: true,
+        }
+    }
+}
+
+/// The error returned when a resolution runs into one of the limits set in
+/// `ResolverConfig`, which is most likely caused by a broken or malicious
+/// delegation rather than by an unreachable server
+#[derive(Debug)]
+pub struct LimitExceeded(pub String);
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Called with every response received while resolving, or the error for a
+/// server that gave none, along with the address of the server asked
+pub type Trace<'a> = &'a mut dyn FnMut(SocketAddr, &Result<DnsPacket>);
+
+/// What a single resolution has left to spend, shared by the nested lookups
+/// it makes along the way
+struct Budget<'a> {
+    deadline: Instant,
+    queries: usize,
+    ns_depth: usize,
+    trace: Option<Trace<'a>>,
+}
+
+impl<'a> Budget<'a> {
+    fn new(config: &ResolverConfig, trace: Option<Trace<'a>>) -> Budget<'a> {
+        Budget {
+            deadline: Instant::now() + config.resolution_timeout,
+            queries: 0,
+            ns_depth: 0,
+            trace,
+        }
+    }
+}
+
+/// How a single query is sent
+#[derive(Copy, Clone, Debug)]
+pub struct QueryOptions {
+    /// How long to wait for the response
+    pub timeout: Duration,
+    /// Whether to ask the server to recurse for us
+    pub recursion_desired: bool,
+    /// Whether to go straight to TCP, rather than only when the response
+    /// doesn't fit in a datagram
+    pub tcp: bool,
+}
+
+impl QueryOptions {
+    pub fn new(timeout: Duration) -> QueryOptions {
+        QueryOptions {
+            timeout,
+            recursion_desired: true,
+            tcp: false,
+        }
+    }
+}
+
+/// Send a single query to a server and return its response, retrying without
+/// EDNS or over TCP when the response calls for it. The timeout covers the
+/// whole exchange, retries included.
+pub fn lookup(
+    query_name: &str,
+    query_type: QueryType,
+    server: SocketAddr,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    if options.tcp {
+        return send_tcp_query(query_name, query_type, server, options);
+    }
+
+    // Each retry only gets what is left of the timeout
+    let deadline = Instant::now() + options.timeout;
+    let remaining = || -> Result<QueryOptions> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        if timeout.is_zero() {
+            return Err(format!("Timed out waiting for a response from {}", server).into());
+        }
+        Ok(QueryOptions {
+            timeout,
+            ..*options
+        })
+    };
+
+    let mut response = send_query(query_name, query_type, server, true, options)?;
+
+    // Servers that predate EDNS(0) may choke on the OPT record and answer with
+    // FORMERR or NOTIMP and no OPT of their own. RFC 6891 asks us to retry
+    // without it in that case.
+    if response.edns.is_none()
+        && matches!(
+            response.header.rescode,
+            ResultCode::FORMERR | ResultCode::NOTIMP
+        )
+    {
+        response = send_query(query_name, query_type, server, false, &remaining()?)?;
+    }
+
+    // If the answer didn't fit in a datagram, the server sets the TC bit and
+    // we have to ask again over TCP to get all of it.
+    if response.header.truncated_message {
+        return send_tcp_query(query_name, query_type, server, &remaining()?);
+    }
+
+    Ok(response)
+}
 
-    // Build our query packet. It's important that we remember to set the
-    // `recursion_desired` flag. As noted earlier, the packet id is arbitrary.
+// Build our query packet. It's important that we remember to set the
+// `recursion_desired` flag when asking a recursive resolver. The packet id is
+// random, so that an attacker who can't see our traffic has to guess it to
+// forge a response.
+fn build_query(query_name: &str, query_type: QueryType, recursion_desired: bool) -> DnsPacket {
     let mut packet = DnsPacket::new();
-    packet.header.id = 6666;
+    packet.header.id = random_u16();
     packet.header.questions = 1;
-    packet.header.recursion_desired = true;
+    packet.header.recursion_desired = recursion_desired;
     packet
         .questions
         .push(DnsQuestion::new(query_name.to_string(), query_type));
+    packet
+}
+
+// Send a single query to a server over UDP and wait for its response
+fn send_query(
+    query_name: &str,
+    query_type: QueryType,
+    server: SocketAddr,
+    use_edns: bool,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    let socket = bind_random_port(server.ip())?;
+    let timeout = options.timeout;
+
+    let mut packet = build_query(query_name, query_type, options.recursion_desired);
+
+    // Advertise that we can take responses larger than 512 bytes
+    if use_edns {
+        packet.set_edns(MAX_UDP_PAYLOAD_SIZE, false);
+    }
 
     // Use our new write method to write the packet to a buffer...
     let mut req_buffer = BytePacketBuffer::new();
     packet.write(&mut req_buffer)?;
 
     // ...and send it off to the server using our socket:
-    socket.send_to(&req_buffer.buf[0..req_buffer.pos], server)?;
+    socket.send_to(req_buffer.as_bytes(), server)?;
+
+    // To prepare for receiving the response, we'll need room for the payload
+    // size we advertised. Only the bytes actually received are then wrapped
+    // in a `BytePacketBuffer`.
+    let mut raw = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+    let started = Instant::now();
+    loop {
+        // Ignored packets don't buy the server more time, so we only wait for
+        // whatever is left of the timeout
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            return Err(format!("Timed out waiting for a response from {}", server).into());
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (len, src) = socket.recv_from(&mut raw)?;
+
+        // Anyone can send a datagram to our port, so anything that doesn't
+        // come from the server we asked or doesn't answer our exact question
+        // is ignored, and we carry on waiting for the real response.
+        if src != server {
+            log_debug!("ignoring response from unexpected address {}", src);
+            continue;
+        }
+
+        //`DnsPacket::from_buffer()` is used to parse the response
+        let mut res_buffer = BytePacketBuffer::from_slice(&raw[..len]);
+        match DnsPacket::from_buffer(&mut res_buffer) {
+            Ok(response) if is_response_to(&packet, &response) => return Ok(response),
+            _ => log_debug!("ignoring mismatched response from {}", src),
+        }
+    }
+}
+
+// Bind a UDP socket to a random port, which together with the random packet
+// id makes forged responses much harder to get accepted (RFC 5452). If we're
+// unlucky enough to pick ports that are in use, the OS picks one for us.
+// The socket is of the same address family as the server we'll talk to.
+fn bind_random_port(server: IpAddr) -> Result<UdpSocket> {
+    let local = match server {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    for _ in 0..10 {
+        let port = random_range(1024, 65536) as u16;
+        match UdpSocket::bind((local, port)) {
+            Ok(socket) => return Ok(socket),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(UdpSocket::bind((local, 0))?)
+}
+
+// Check that a response carries the id of our query and repeats its question
+fn is_response_to(query: &DnsPacket, response: &DnsPacket) -> bool {
+    response.header.response
+        && response.header.id == query.header.id
+        && response.questions.len() == query.questions.len()
+        && response
+            .questions
+            .iter()
+            .zip(query.questions.iter())
+            .all(|(a, b)| {
+                a.question_type == b.question_type && a.name.eq_ignore_ascii_case(&b.name)
+            })
+}
+
+// Send a single query to a server over TCP and wait for its response. TCP
+// messages are prefixed by their length and can be up to 65535 bytes long.
+fn send_tcp_query(
+    query_name: &str,
+    query_type: QueryType,
+    server: SocketAddr,
+    options: &QueryOptions,
+) -> Result<DnsPacket> {
+    let mut stream = TcpStream::connect_timeout(&server, options.timeout)?;
+    stream.set_read_timeout(Some(options.timeout))?;
+    stream.set_write_timeout(Some(options.timeout))?;
+
+    let packet = build_query(query_name, query_type, options.recursion_desired);
+    let mut req_buffer = BytePacketBuffer::new();
+    packet.write(&mut req_buffer)?;
+    write_message(&mut stream, req_buffer.as_bytes())?;
+
+    let message = read_message(&mut stream)?;
+    let mut res_buffer = BytePacketBuffer::from_slice(&message);
+    let response = DnsPacket::from_buffer(&mut res_buffer)?;
+
+    if !is_response_to(&packet, &response) {
+        return Err("Response over TCP doesn't match our query".into());
+    }
+
+    Ok(response)
+}
+
+// The addresses to send queries to for the given name servers
+fn socket_addrs(servers: &[IpAddr]) -> Vec<SocketAddr> {
+    servers
+        .iter()
+        .map(|&ip| SocketAddr::new(ip, DNS_PORT))
+        .collect()
+}
+
+// Ask each of the given servers in turn until one of them answers, making
+// up to `config.retries` more rounds if none do
+fn query_servers(
+    qname: &str,
+    qtype: QueryType,
+    servers: &[SocketAddr],
+    config: &ResolverConfig,
+    budget: &mut Budget,
+) -> Result<DnsPacket> {
+    let mut last_error = None;
+
+    for _ in 0..=config.retries {
+        for &ns in servers {
+            let now = Instant::now();
+            if now >= budget.deadline {
+                return Err(format!("Timed out resolving {:?} {}", qtype, qname).into());
+            }
+            let timeout = config.query_timeout.min(budget.deadline - now);
+
+            if budget.queries >= config.max_queries {
+                return Err(LimitExceeded(format!(
+                    "Sent {} queries without resolving {:?} {}",
+                    budget.queries, qtype, qname
+                ))
+                .into());
+            }
+            budget.queries += 1;
+
+            log_debug!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+
+            let result = lookup(qname, qtype, ns, &QueryOptions::new(timeout));
+            if let Some(trace) = budget.trace.as_mut() {
+                trace(ns, &result);
+            }
+
+            match result {
+                // A server that fails or refuses to answer is treated like one
+                // that didn't answer at all, and we move on to the next one
+                Ok(response)
+                    if matches!(
+                        response.header.rescode,
+                        ResultCode::SERVFAIL | ResultCode::REFUSED
+                    ) =>
+                {
+                    log_debug!("ns {} answered {:?}", ns, response.header.rescode);
+                    last_error = Some(format!("{:?} from {}", response.header.rescode, ns).into());
+                }
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    log_debug!("lookup with ns {} failed: {}", ns, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| "No name servers to query".into()))
+}
+
+/// Ask the servers from the root hints for the current list of root servers
+/// (RFC 8109), and cache the NS records and addresses they answer with.
+/// Resolution then starts from the cached list rather than from the hints,
+/// which may be out of date. Returns the TTL of the root NS records, after
+/// which priming should be done again.
+pub fn prime_root_servers(cache: &DnsCache, config: &ResolverConfig) -> Result<u32> {
+    let mut servers = config.root_hints.addresses();
+    config.ip_policy.order(&mut servers);
+
+    let mut budget = Budget::new(config, None);
+    let response = query_servers(
+        "",
+        QueryType::NS,
+        &socket_addrs(&servers),
+        config,
+        &mut budget,
+    )?;
+
+    let root_ns: Vec<DnsRecord> = response
+        .answers
+        .iter()
+        .filter(|record| matches!(record, DnsRecord::NS { domain, .. } if domain.is_empty()))
+        .cloned()
+        .collect();
+    if root_ns.is_empty() {
+        return Err("Priming response holds no NS records for the root".into());
+    }
+
+    // Only addresses of the root servers themselves are worth keeping
+    let hosts: Vec<String> = root_ns
+        .iter()
+        .filter_map(|record| match record {
+            DnsRecord::NS { host, .. } => Some(host.to_lowercase()),
+            _ => None,
+        })
+        .collect();
+    let glue: Vec<DnsRecord> = response
+        .resources
+        .iter()
+        .filter(|record| matches!(record, DnsRecord::A { .. } | DnsRecord::AAAA { .. }))
+        .filter(|record| hosts.contains(&record.domain().to_lowercase()))
+        .cloned()
+        .collect();
+    if glue.is_empty() {
+        return Err("Priming response holds no root server addresses".into());
+    }
 
-    // To prepare for receiving the response, we'll create a new `BytePacketBuffer`,
-    // and ask the socket to write the response directly into our buffer.
-    let mut res_buffer = BytePacketBuffer::new();
-    socket.recv_from(&mut res_buffer.buf)?;
+    let ttl = root_ns.iter().map(|record| record.ttl()).min().unwrap_or(0);
+    cache.store(&root_ns);
+    cache.store(&glue);
 
-    //`DnsPacket::from_buffer()` is used to parse the response
-    DnsPacket::from_buffer(&mut res_buffer)
+    Ok(ttl)
 }
 
 // Recursively query name servers until we get an answer or hit an error
-pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
-    // For now we're always starting with *a.root-servers.net*.
-    let mut ns = "198.41.0.4".parse::<Ipv4Addr>().unwrap();
+pub fn recursive_lookup(
+    qname: &str,
+    qtype: QueryType,
+    cache: &DnsCache,
+    config: &ResolverConfig,
+) -> Result<DnsPacket> {
+    resolve_with_trace(qname, qtype, cache, config, None)
+}
+
+/// The same as `recursive_lookup`, but every response received on the way
+/// is handed to `trace` as well, to show how the answer was found
+pub fn recursive_lookup_traced(
+    qname: &str,
+    qtype: QueryType,
+    cache: &DnsCache,
+    config: &ResolverConfig,
+    trace: Trace,
+) -> Result<DnsPacket> {
+    resolve_with_trace(qname, qtype, cache, config, Some(trace))
+}
+
+fn resolve_with_trace(
+    qname: &str,
+    qtype: QueryType,
+    cache: &DnsCache,
+    config: &ResolverConfig,
+    trace: Option<Trace>,
+) -> Result<DnsPacket> {
+    // The deadline and limits cover the whole resolution, including the
+    // lookups of name server addresses we may have to make along the way
+    let mut budget = Budget::new(config, trace);
 
-    // Since it might take an arbitrary number of steps, we enter an unbounded loop.
+    // A server may answer with only an alias for the name, possibly in a zone
+    // it isn't authoritative for. In that case resolution starts over at the
+    // target of the alias, and the CNAMEs are put in front of whatever the
+    // target resolves to, so the client gets the whole chain at once.
+    let mut chain = Vec::new();
+    let mut seen = vec![qname.to_lowercase()];
+    let mut name = qname.to_string();
     loop {
-        println!("attempting lookup of {:?} {} with ns {}", qtype, qname, ns);
+        let mut response = resolve(&name, qtype, cache, config, &mut budget)?;
 
-        // The next step is to send the query to the active server.
-        let ns_copy = ns;
+        let target = follow_cnames(&name, &response.answers, &mut seen)?;
+        let answered = response
+            .answers
+            .iter()
+            .any(|rec| rec.query_type() == qtype && rec.domain().eq_ignore_ascii_case(&target));
 
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server)?;
+        chain.append(&mut response.answers);
+
+        if qtype == QueryType::CNAME
+            || answered
+            || target == name.to_lowercase()
+            || response.header.rescode != ResultCode::NOERROR
+        {
+            response.answers = chain;
+            return Ok(response);
+        }
+
+        log_debug!("following CNAME from {} to {}", name, target);
+        name = target;
+    }
+}
+
+// Walk the CNAME records in `answers` starting at `name` and return the name
+// at the end of the chain. Every name visited is added to `seen`, which spans
+// the whole resolution, so that loops across responses are caught as well.
+fn follow_cnames(name: &str, answers: &[DnsRecord], seen: &mut Vec<String>) -> Result<String> {
+    let mut current = name.to_lowercase();
+
+    while let Some(host) = answers.iter().find_map(|rec| match rec {
+        DnsRecord::CNAME { domain, host, .. } if domain.eq_ignore_ascii_case(&current) => {
+            Some(host.to_lowercase())
+        }
+        _ => None,
+    }) {
+        if seen.contains(&host) {
+            return Err(format!("CNAME loop at {}", host).into());
+        }
+        if seen.len() > MAX_CNAME_CHAIN {
+            return Err(format!("CNAME chain for {} is too long", name).into());
+        }
+        seen.push(host.clone());
+        current = host;
+    }
+
+    Ok(current)
+}
+
+fn resolve(
+    qname: &str,
+    qtype: QueryType,
+    cache: &DnsCache,
+    config: &ResolverConfig,
+    budget: &mut Budget,
+) -> Result<DnsPacket> {
+    // Anything we've seen recently and that is still within its TTL can be
+    // answered without touching the network at all.
+    if let Some(cached) = cache.lookup(qname, qtype) {
+        log_debug!("cache hit for {:?} {}", qtype, qname);
+        return Ok(cached);
+    }
+
+    // Otherwise start with the closest zone we already know name servers for,
+    // falling back to the root hints. We keep track of the zone the servers
+    // we're talking to are authoritative for, since they can't be trusted
+    // with anything outside of it.
+    let policy = config.ip_policy;
+    let (mut zone, mut servers) = cache.lookup_closest_ns(qname).unwrap_or_default();
+    policy.order(&mut servers);
+    if servers.is_empty() {
+        zone = String::new();
+        servers = config.root_hints.addresses();
+        policy.order(&mut servers);
+    }
+
+    // With QNAME minimisation, each server is only asked about the name one
+    // label below the zone it serves, until we reach the full name. Servers
+    // that answer those queries with anything but a referral or a plain
+    // NOERROR are likely broken, so we relax and send the full name from
+    // then on, rather than failing the resolution.
+    let mut minimise = config.qname_minimisation;
+    let mut extra_labels = 1;
+    let mut minimised_queries = 0;
+
+    // Since it might take an arbitrary number of steps, we enter a loop, which
+    // is only bounded by the number of referrals we're willing to follow.
+    let mut referrals = 0;
+    loop {
+        let mut query_name = qname.to_string();
+        let mut query_type = qtype;
+        if minimise && minimised_queries < MAX_MINIMISE_COUNT {
+            let name = minimised_name(qname, &zone, extra_labels);
+            if name.len() < qname.len() {
+                // RFC 9156 recommends asking for A records, which is what
+                // servers are least likely to trip over
+                query_name = name;
+                query_type = QueryType::A;
+                minimised_queries += 1;
+            }
+        }
+        let minimised = query_name.len() < qname.len();
+
+        // The next step is to send the query to the active servers.
+        let mut response = match query_servers(
+            &query_name,
+            query_type,
+            &socket_addrs(&servers),
+            config,
+            budget,
+        ) {
+            Ok(response) => response,
+            Err(e) if minimised && !e.is::<LimitExceeded>() => {
+                log_debug!(
+                    "minimised query for {} failed, sending the full name",
+                    qname
+                );
+                minimise = false;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        response.discard_out_of_zone(&zone);
+
+        // Remember everything the server told us, including referrals and glue,
+        // so that later lookups can start further down the tree.
+        cache.store(&response.answers);
+        cache.store(&response.authorities);
+        cache.store(&response.resources);
+        cache.store_negative(&query_name, query_type, &response);
+
+        if minimised {
+            let referral = response
+                .get_delegation(qname)
+                .filter(|delegation| is_strict_subdomain(delegation, &zone));
+
+            match response.header.rescode {
+                // Following a referral works the same as for a full query
+                ResultCode::NOERROR if referral.is_some() => {}
+                // The name we asked about is in the same zone as the server, so
+                // we ask again with one more label
+                ResultCode::NOERROR => {
+                    extra_labels += 1;
+                    continue;
+                }
+                // An NXDOMAIN should mean that nothing below the name exists
+                // either (RFC 8020), but some servers give it for names that
+                // merely have no records of their own
+                _ => {
+                    log_debug!(
+                        "minimised query for {} got {:?}, sending the full name",
+                        qname,
+                        response.header.rescode
+                    );
+                    minimise = false;
+                    continue;
+                }
+            }
+        }
 
         // If there are entries in the answer section, and no errors, we are done!
         if !response.answers.is_empty() && response.header.rescode == ResultCode::NOERROR {
@@ -65,33 +690,316 @@ pub fn recursive_lookup(qname: &str, qtype: QueryType) -> Result<DnsPacket> {
             return Ok(response);
         }
 
-        // Otherwise, we'll try to find a new nameserver based on NS and a corresponding A
-        // record in the additional section. If this succeeds, we can switch name server
-        // and retry the loop.
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
+        // A referral has to take us further down the tree, towards `qname`.
+        // Anything else is a lame or broken server, and following it could
+        // send us round in circles, so we go with what it told us.
+        let delegation = match response.get_delegation(qname) {
+            Some(delegation) if is_strict_subdomain(delegation, &zone) => delegation.to_lowercase(),
+            _ => return Ok(response),
+        };
+
+        referrals += 1;
+        if referrals > config.max_referrals {
+            return Err(LimitExceeded(format!(
+                "Followed {} referrals without resolving {:?} {}",
+                config.max_referrals, qtype, qname
+            ))
+            .into());
+        }
+
+        // Otherwise, we'll try to find new nameservers based on NS and corresponding A
+        // and AAAA records in the additional section. If this succeeds, we can switch
+        // name servers and retry the loop.
+        let mut resolved = response.get_resolved_ns(qname);
+        policy.order(&mut resolved);
+        if !resolved.is_empty() {
+            servers = resolved;
+            zone = delegation;
+            extra_labels = 1;
 
             continue;
         }
 
-        // If not, we'll have to resolve the ip of a NS record. If no NS records exist,
-        // we'll go with what the last server told us.
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
-            None => return Ok(response),
-        };
+        // If not, we'll have to resolve the ip of a NS record. Since name servers
+        // may live in zones whose name servers have to be resolved in turn, the
+        // nesting is limited, or a delegation loop would never end.
+        let ns_names = response.get_unresolved_ns(qname);
+        if budget.ns_depth >= config.max_ns_depth {
+            return Err(LimitExceeded(format!(
+                "Name server lookups for {:?} {} nest too deeply",
+                qtype, qname
+            ))
+            .into());
+        }
 
         // Here we go down the rabbit hole by starting _another_ lookup sequence in the
         // midst of our current one. Hopefully, this will give us the IP of an appropriate
-        // name server.
-        let recursive_response = recursive_lookup(&new_ns_name, QueryType::A)?;
-
-        // Finally, we pick a random ip from the result, and restart the loop. If no such
-        // record is available, we again return the last result we got.
-        if let Some(new_ns) = recursive_response.get_random_a_record() {
-            ns = new_ns;
-        } else {
+        // name server. If one name server can't be resolved, we try the next.
+        let mut new_servers = Vec::new();
+        budget.ns_depth += 1;
+        'names: for ns_name in ns_names {
+            for address_type in policy.address_types() {
+                match resolve(ns_name, address_type, cache, config, budget) {
+                    Ok(recursive_response) => new_servers = recursive_response.get_addresses(),
+                    // Running out of budget ends the whole resolution
+                    Err(e) if e.is::<LimitExceeded>() => return Err(e),
+                    Err(e) => log_debug!("failed to resolve name server {}: {}", ns_name, e),
+                }
+                policy.order(&mut new_servers);
+                if !new_servers.is_empty() {
+                    break 'names;
+                }
+            }
+        }
+        budget.ns_depth -= 1;
+
+        // Finally, we restart the loop with the addresses we found. If there are
+        // none, we again return the last result we got.
+        if new_servers.is_empty() {
             return Ok(response);
         }
+        servers = new_servers;
+        zone = delegation;
+        extra_labels = 1;
+    }
+}
+
+// The name made of `qname`'s labels in `zone` and `extra_labels` more, or all
+// of `qname` if it doesn't have that many labels
+fn minimised_name(qname: &str, zone: &str, extra_labels: usize) -> String {
+    let labels: Vec<&str> = qname.split('.').filter(|label| !label.is_empty()).collect();
+    let zone_labels = zone.split('.').filter(|label| !label.is_empty()).count();
+
+    let keep = (zone_labels + extra_labels).min(labels.len());
+    labels[labels.len() - keep..].join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_is_response_to() {
+        let query = build_query("www.example.com", QueryType::A, true);
+
+        let mut response = build_query("WWW.example.com", QueryType::A, true);
+        response.header.id = query.header.id;
+        response.header.response = true;
+        assert!(is_response_to(&query, &response));
+
+        response.header.id = query.header.id.wrapping_add(1);
+        assert!(!is_response_to(&query, &response));
+
+        let mut response = build_query("www.example.org", QueryType::A, true);
+        response.header.id = query.header.id;
+        response.header.response = true;
+        assert!(!is_response_to(&query, &response));
+    }
+
+    fn cname(domain: &str, host: &str) -> DnsRecord {
+        DnsRecord::CNAME {
+            domain: domain.to_string(),
+            host: host.to_string(),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn test_follow_cnames() {
+        let answers = vec![
+            cname("b.example.com", "c.example.net"),
+            cname("www.example.com", "b.example.com"),
+        ];
+        let mut seen = vec!["www.example.com".to_string()];
+        let target = follow_cnames("www.example.com", &answers, &mut seen).unwrap();
+        assert_eq!(target, "c.example.net");
+        assert_eq!(seen.len(), 3);
+
+        // A later response pointing back into the chain is a loop
+        let answers = vec![cname("c.example.net", "WWW.example.com")];
+        assert!(follow_cnames("c.example.net", &answers, &mut seen).is_err());
+    }
+
+    #[test]
+    fn test_follow_cnames_limits_length() {
+        let answers: Vec<DnsRecord> = (0..20)
+            .map(|i| {
+                cname(
+                    &format!("{}.example.com", i),
+                    &format!("{}.example.com", i + 1),
+                )
+            })
+            .collect();
+        let mut seen = vec!["0.example.com".to_string()];
+        assert!(follow_cnames("0.example.com", &answers, &mut seen).is_err());
+    }
+
+    #[test]
+    fn test_minimised_name() {
+        assert_eq!(minimised_name("www.example.com", "", 1), "com");
+        assert_eq!(minimised_name("www.example.com", "com", 1), "example.com");
+        assert_eq!(
+            minimised_name("www.example.com", "com", 2),
+            "www.example.com"
+        );
+        assert_eq!(
+            minimised_name("www.example.com", "com", 5),
+            "www.example.com"
+        );
+    }
+
+    #[test]
+    fn test_query_limit() {
+        let config = ResolverConfig {
+            max_queries: 0,
+            ..ResolverConfig::default()
+        };
+
+        let err = recursive_lookup("www.example.com", QueryType::A, &DnsCache::new(), &config)
+            .unwrap_err();
+        assert!(err.is::<LimitExceeded>());
+    }
+
+    #[test]
+    fn test_ip_policy_order() {
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let mut servers = vec![v6, v4];
+        IpPolicy::PreferIpv4.order(&mut servers);
+        assert_eq!(servers, vec![v4, v6]);
+
+        let mut servers = vec![v4, v6];
+        IpPolicy::PreferIpv6.order(&mut servers);
+        assert_eq!(servers, vec![v6, v4]);
+
+        let mut servers = vec![v4, v6];
+        IpPolicy::Ipv6Only.order(&mut servers);
+        assert_eq!(servers, vec![v6]);
+    }
+
+    // A server of our own, which only answers when a test says so
+    fn bind_server() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    // How many queries a server that never answers has received
+    fn received(socket: &UdpSocket) -> usize {
+        socket.set_nonblocking(true).unwrap();
+        let mut raw = [0; 512];
+        let mut count = 0;
+        while socket.recv_from(&mut raw).is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    // Answer the next query that arrives on `socket` after `delay`, with
+    // `rescode` and no OPT record
+    fn answer_one(
+        socket: UdpSocket,
+        rescode: ResultCode,
+        delay: Duration,
+    ) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut raw = [0; 512];
+            let (len, src) = socket.recv_from(&mut raw).unwrap();
+            let mut packet =
+                DnsPacket::from_buffer(&mut BytePacketBuffer::from_slice(&raw[..len])).unwrap();
+            packet.header.response = true;
+            packet.header.rescode = rescode;
+            packet.edns = None;
+
+            thread::sleep(delay);
+            let mut buffer = BytePacketBuffer::new();
+            packet.write(&mut buffer).unwrap();
+            socket.send_to(buffer.as_bytes(), src).unwrap();
+        })
+    }
+
+    fn test_config() -> ResolverConfig {
+        ResolverConfig {
+            query_timeout: Duration::from_millis(100),
+            retries: 0,
+            ..ResolverConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_query_servers_fails_over() {
+        let (silent, silent_addr) = bind_server();
+        let (answering, answering_addr) = bind_server();
+        let config = test_config();
+        let server = answer_one(answering, ResultCode::NOERROR, Duration::ZERO);
+
+        let servers = [silent_addr, answering_addr];
+        let mut hops = Vec::new();
+        let mut trace =
+            |ns: SocketAddr, result: &Result<DnsPacket>| hops.push((ns, result.is_ok()));
+        let mut budget = Budget::new(&config, Some(&mut trace));
+        let response =
+            query_servers("example.com", QueryType::A, &servers, &config, &mut budget).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert_eq!(budget.queries, 2);
+        assert_eq!(received(&silent), 1);
+        // The server that timed out is traced too
+        assert_eq!(hops, vec![(servers[0], false), (servers[1], true)]);
+    }
+
+    #[test]
+    fn test_query_servers_retries() {
+        let (silent, addr) = bind_server();
+        let config = ResolverConfig {
+            query_timeout: Duration::from_millis(50),
+            retries: 2,
+            ..test_config()
+        };
+
+        let servers = [addr];
+        let mut budget = Budget::new(&config, None);
+        assert!(
+            query_servers("example.com", QueryType::A, &servers, &config, &mut budget).is_err()
+        );
+        assert_eq!(budget.queries, 3);
+        assert_eq!(received(&silent), 3);
+    }
+
+    #[test]
+    fn test_query_servers_deadline() {
+        let (silent, addr) = bind_server();
+        let config = ResolverConfig {
+            query_timeout: Duration::from_secs(5),
+            retries: 5,
+            resolution_timeout: Duration::from_millis(150),
+            ..test_config()
+        };
+
+        let servers = [addr];
+        let mut budget = Budget::new(&config, None);
+        let started = Instant::now();
+        assert!(
+            query_servers("example.com", QueryType::A, &servers, &config, &mut budget).is_err()
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(received(&silent), 1);
+    }
+
+    #[test]
+    fn test_lookup_timeout_covers_retry() {
+        // A server that takes most of the timeout to answer FORMERR, and then
+        // never answers the retry without EDNS
+        let (socket, addr) = bind_server();
+        let server = answer_one(socket, ResultCode::FORMERR, Duration::from_millis(150));
+
+        let started = Instant::now();
+        let options = QueryOptions::new(Duration::from_millis(250));
+        assert!(lookup("example.com", QueryType::A, addr, &options).is_err());
+        assert!(started.elapsed() < Duration::from_millis(350));
+        server.join().unwrap();
     }
 }
@@ -1,13 +1,24 @@
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
 
 use crate::buffer::buffer::BytePacketBuffer;
+use crate::log_debug;
 use crate::utils::types::Result;
 
 use super::dns_header::DnsHeader;
 use super::dns_question::DnsQuestion;
 use super::dns_record::DnsRecord;
+use super::domain_name::is_subdomain;
+use super::edns_option::EdnsOption;
 use super::query_type::QueryType;
 
+/// The largest UDP payload we are willing to send or receive when EDNS(0)
+/// is in use
+pub const MAX_UDP_PAYLOAD_SIZE: u16 = 4096;
+
+/// Without EDNS(0), UDP messages are limited to 512 bytes
+pub const DEFAULT_UDP_PAYLOAD_SIZE: u16 = 512;
+
 ///DnsPacket wraps everything together
 #[derive(Clone, Debug)]
 pub struct DnsPacket {
@@ -16,6 +27,15 @@ pub struct DnsPacket {
     pub answers: Vec<DnsRecord>,
     pub authorities: Vec<DnsRecord>,
     pub resources: Vec<DnsRecord>,
+    // The OPT pseudo-record is kept apart from the additional section, since
+    // it describes the packet rather than the data in it
+    pub edns: Option<DnsRecord>,
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DnsPacket {
@@ -26,6 +46,7 @@ impl DnsPacket {
             answers: Vec::new(),
             authorities: Vec::new(),
             resources: Vec::new(),
+            edns: None,
         }
     }
     // read DNS packet from buffer
@@ -49,6 +70,13 @@ impl DnsPacket {
         }
         for _ in 0..result.header.resource_entries {
             let rec = DnsRecord::read(buffer)?;
+            if let DnsRecord::OPT { .. } = rec {
+                if result.edns.is_some() {
+                    return Err("Packet contains more than one OPT record".into());
+                }
+                result.edns = Some(rec);
+                continue;
+            }
             result.resources.push(rec);
         }
 
@@ -56,8 +84,14 @@ impl DnsPacket {
     }
     // write DNS packet to buffer
     pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
-        // Write header
-        self.header.write(buffer)?;
+        // Write header, with the counts taken from the sections we're about
+        // to write rather than trusting whatever the header was left with
+        let mut header = self.header.clone();
+        header.questions = self.questions.len() as u16;
+        header.answers = self.answers.len() as u16;
+        header.authoritative_entries = self.authorities.len() as u16;
+        header.resource_entries = (self.resources.len() + self.edns.iter().count()) as u16;
+        header.write(buffer)?;
         // Write questions
         for question in &self.questions {
             question.write(buffer)?;
@@ -74,20 +108,158 @@ impl DnsPacket {
         for resource in &self.resources {
             resource.write(buffer)?;
         }
+        // write the OPT pseudo-record, if any
+        if let Some(ref opt) = self.edns {
+            opt.write(buffer)?;
+        }
         Ok(())
     }
 
+    /// Write the packet like `write`, but if it doesn't fit in the capacity of
+    /// the buffer, leave out whole records from the end until it does. The TC
+    /// bit tells the client to retry over TCP, and is only set when answer or
+    /// authority records had to go: additional records are optional, so
+    /// losing them isn't truncation (RFC 2181 section 9). The OPT record is
+    /// always kept. Returns whether any records were left out.
+    pub fn write_truncated(&self, buffer: &mut BytePacketBuffer) -> Result<bool> {
+        let start = buffer.len();
+        let capacity = buffer.capacity();
+
+        // Work out how much room the OPT record needs so we can keep it aside
+        let mut opt_buffer = BytePacketBuffer::growable();
+        if let Some(ref opt) = self.edns {
+            opt.write(&mut opt_buffer)?;
+        }
+        let opt_size = opt_buffer.len();
+        if start + 12 + opt_size > capacity {
+            return Err("Buffer is too small for even an empty response".into());
+        }
+
+        // The header is written with placeholder counts, and rewritten once we
+        // know how many records made it in
+        let mut header = self.header.clone();
+        header.write(buffer)?;
+
+        buffer.set_capacity(capacity - opt_size);
+
+        for question in &self.questions {
+            question.write(buffer)?;
+        }
+
+        let mut counts = [0u16; 3];
+        let mut truncated = false;
+        let mut incomplete = false;
+        let sections = [&self.answers, &self.authorities, &self.resources];
+        'sections: for (idx, (section, count)) in sections.iter().zip(counts.iter_mut()).enumerate()
+        {
+            for record in section.iter() {
+                let mark = buffer.len();
+                if let Err(e) = record.write(buffer) {
+                    buffer.truncate(mark);
+
+                    // Only running out of room is a reason to truncate, any
+                    // other problem with the record is a real error
+                    if record.write(&mut BytePacketBuffer::growable()).is_err() {
+                        return Err(e);
+                    }
+
+                    truncated = true;
+                    incomplete = idx < 2;
+                    break 'sections;
+                }
+                *count += 1;
+            }
+        }
+
+        buffer.set_capacity(capacity);
+        if let Some(ref opt) = self.edns {
+            opt.write(buffer)?;
+        }
+
+        let end = buffer.len();
+        header.truncated_message = header.truncated_message || incomplete;
+        header.questions = self.questions.len() as u16;
+        header.answers = counts[0];
+        header.authoritative_entries = counts[1];
+        header.resource_entries = counts[2] + self.edns.iter().count() as u16;
+        buffer.seek(start)?;
+        header.write(buffer)?;
+        buffer.seek(end)?;
+
+        Ok(truncated)
+    }
+
+    /// Attach an OPT record advertising the given UDP payload size
+    pub fn set_edns(&mut self, udp_payload_size: u16, dnssec_ok: bool) {
+        self.edns = Some(DnsRecord::OPT {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok,
+            options: Vec::new(),
+        });
+    }
+
+    /// Attach an option to the OPT record. Options can only be sent to
+    /// clients that speak EDNS(0), so without an OPT record this does nothing.
+    pub fn add_edns_option(&mut self, option: EdnsOption) {
+        if let Some(DnsRecord::OPT {
+            ref mut options, ..
+        }) = self.edns
+        {
+            options.push(option);
+        }
+    }
+
+    /// The largest UDP message the sender of this packet is able to receive.
+    /// RFC 6891 says advertised sizes below 512 are to be treated as 512.
+    pub fn max_udp_payload_size(&self) -> u16 {
+        match self.edns {
+            Some(DnsRecord::OPT {
+                udp_payload_size, ..
+            }) => udp_payload_size.max(DEFAULT_UDP_PAYLOAD_SIZE),
+            _ => DEFAULT_UDP_PAYLOAD_SIZE,
+        }
+    }
+
     /// It's useful to be able to pick a random A record from a packet. When we
     /// get multiple IP's for a single name, it doesn't matter which one we
     /// choose, so in those cases we can now pick one at random.
     pub fn get_random_a_record(&self) -> Option<Ipv4Addr> {
+        self.get_a_records().into_iter().next()
+    }
+
+    /// All the addresses in the A records of the answer section
+    pub fn get_a_records(&self) -> Vec<Ipv4Addr> {
         self.answers
             .iter()
             .filter_map(|record| match record {
                 DnsRecord::A { addr, .. } => Some(*addr),
                 _ => None,
             })
-            .next()
+            .collect()
+    }
+
+    /// All the addresses in the A and AAAA records of the answer section
+    pub fn get_addresses(&self) -> Vec<IpAddr> {
+        self.answers.iter().filter_map(record_address).collect()
+    }
+
+    /// Negative answers carry the SOA record of the zone in the authority
+    /// section, which tells us how long the absence of a record may be cached.
+    pub fn get_soa(&self) -> Option<&DnsRecord> {
+        self.authorities
+            .iter()
+            .find(|record| matches!(record, DnsRecord::SOA { .. }))
+    }
+
+    /// The TTL for caching a negative answer, which RFC 2308 defines as the
+    /// lesser of the SOA record's own TTL and its MINIMUM field
+    pub fn get_negative_ttl(&self) -> Option<u32> {
+        match self.get_soa() {
+            Some(DnsRecord::SOA { ttl, minimum, .. }) => Some((*ttl).min(*minimum)),
+            _ => None,
+        }
     }
 
     /// A helper function which returns an iterator over all name servers in
@@ -103,42 +275,250 @@ impl DnsPacket {
                 _ => None,
             })
             // Discard servers which aren't authoritative to our query
-            .filter(move |(domain, _)| qname.ends_with(*domain))
+            .filter(move |(domain, _)| is_subdomain(qname, domain))
+    }
+
+    /// The zone that a referral for `qname` delegates to, if the packet is one
+    pub fn get_delegation<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+        self.get_ns(qname).map(|(domain, _)| domain).next()
+    }
+
+    /// Drop every record owned by a name outside of `zone`, which is the zone
+    /// the server was asked about. A server has no say over names outside of
+    /// its zone, and trusting them would let it poison our cache with, say,
+    /// addresses for a bank's name servers (RFC 5452, section 6). The OPT
+    /// record describes the packet itself and is kept.
+    pub fn discard_out_of_zone(&mut self, zone: &str) {
+        let before = self.answers.len() + self.authorities.len() + self.resources.len();
+
+        for section in [
+            &mut self.answers,
+            &mut self.authorities,
+            &mut self.resources,
+        ] {
+            section.retain(|record| is_subdomain(record.domain(), zone));
+        }
+
+        let after = self.answers.len() + self.authorities.len() + self.resources.len();
+        if after < before {
+            log_debug!(
+                "discarded {} records outside of zone {:?}",
+                before - after,
+                zone
+            );
+        }
     }
 
     /// We'll use the fact that name servers often bundle the corresponding
     /// A records when replying to an NS query to implement a function that
-    /// returns the actual IP for an NS record if possible.
-    pub fn get_resolved_ns(&self, qname: &str) -> Option<Ipv4Addr> {
+    /// returns the actual IPs of the NS records where possible. All of them
+    /// are returned, so that we can move on to another server if one fails.
+    pub fn get_resolved_ns(&self, qname: &str) -> Vec<IpAddr> {
         // Get an iterator over the nameservers in the authorities section
         self.get_ns(qname)
-            // Now we need to look for a matching A record in the additional
-            // section. Since we just want the first valid record, we can just
-            // build a stream of matching records.
+            // Now we need to look for matching A and AAAA records in the
+            // additional section.
             .flat_map(|(_, host)| {
                 self.resources
                     .iter()
-                    // Filter for A records where the domain match the host
-                    // of the NS record that we are currently processing
-                    .filter_map(move |record| match record {
-                        DnsRecord::A { domain, addr, .. } if domain == host => Some(addr),
-                        _ => None,
-                    })
+                    // Filter for address records where the domain match the
+                    // host of the NS record that we are currently processing
+                    .filter(move |record| record.domain() == host)
+                    .filter_map(record_address)
             })
-            .map(|addr| *addr)
-            // Finally, pick the first valid entry
-            .next()
+            .collect()
     }
 
     /// However, not all name servers are as that nice. In certain cases there won't
     /// be any A records in the additional section, and we'll have to perform *another*
     /// lookup in the midst. For this, we introduce a method for returning the host
-    /// name of an appropriate name server.
-    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Option<&'a str> {
+    /// names of the appropriate name servers.
+    pub fn get_unresolved_ns<'a>(&'a self, qname: &'a str) -> Vec<&'a str> {
         // Get an iterator over the nameservers in the authorities section
-        self.get_ns(qname)
-            .map(|(_, host)| host)
-            // Finally, pick the first valid entry
-            .next()
+        self.get_ns(qname).map(|(_, host)| host).collect()
+    }
+}
+
+/// A packet in the sectioned layout dig uses, with the header first and
+/// every record in presentation format
+impl fmt::Display for DnsPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = &self.header;
+        let opcode = match header.opcode {
+            0 => "QUERY".to_string(),
+            opcode => opcode.to_string(),
+        };
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {:?}, id: {}",
+            opcode, header.rescode, header.id
+        )?;
+
+        let flags: Vec<&str> = [
+            (header.response, "qr"),
+            (header.authoritative_answer, "aa"),
+            (header.truncated_message, "tc"),
+            (header.recursion_desired, "rd"),
+            (header.recursion_available, "ra"),
+            (header.authed_data, "ad"),
+            (header.checking_disabled, "cd"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+        writeln!(
+            f,
+            ";; flags: {}; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            flags.join(" "),
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.resources.len() + self.edns.iter().count()
+        )?;
+
+        if let Some(ref edns) = self.edns {
+            write!(f, "\n;; OPT PSEUDOSECTION:\n{}\n", edns)?;
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                writeln!(f, "{}", question)?;
+            }
+        }
+
+        let sections = [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.resources),
+        ];
+        for (name, records) in sections {
+            if records.is_empty() {
+                continue;
+            }
+            writeln!(f, "\n;; {} SECTION:", name)?;
+            for record in records {
+                writeln!(f, "{}", record)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The address held by an A or AAAA record
+fn record_address(record: &DnsRecord) -> Option<IpAddr> {
+    match record {
+        DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+        DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip_with_compression() {
+        let mut packet = DnsPacket::new();
+        packet.header.id = 1234;
+        packet.header.response = true;
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), QueryType::MX));
+        packet.answers.push(DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        });
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: "ns1.example.com".to_string(),
+            ttl: 300,
+        });
+
+        let mut buffer = BytePacketBuffer::new();
+        packet.write(&mut buffer).unwrap();
+        // Every repeated "example.com" is replaced by a two byte pointer
+        assert_eq!(buffer.len(), 12 + 17 + (2 + 10 + 2 + 7) + (2 + 10 + 6));
+
+        buffer.seek(0).unwrap();
+        let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert_eq!(parsed.questions, packet.questions);
+        assert_eq!(parsed.answers, packet.answers);
+        assert_eq!(parsed.authorities, packet.authorities);
+    }
+
+    #[test]
+    fn test_write_truncated() {
+        let mut packet = DnsPacket::new();
+        packet
+            .questions
+            .push(DnsQuestion::new("example.com".to_string(), QueryType::A));
+        for i in 0..100 {
+            packet.answers.push(DnsRecord::A {
+                domain: "example.com".to_string(),
+                addr: Ipv4Addr::new(10, 0, 0, i),
+                ttl: 300,
+            });
+        }
+        packet.set_edns(MAX_UDP_PAYLOAD_SIZE, false);
+
+        let mut buffer = BytePacketBuffer::new();
+        assert!(packet.write_truncated(&mut buffer).unwrap());
+        assert!(buffer.len() <= 512);
+
+        buffer.seek(0).unwrap();
+        let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert!(parsed.header.truncated_message);
+        assert_eq!(parsed.header.answers as usize, parsed.answers.len());
+        assert!(parsed.answers.len() < 100);
+        assert!(parsed.edns.is_some());
+
+        let mut buffer = BytePacketBuffer::with_capacity(MAX_UDP_PAYLOAD_SIZE as usize);
+        assert!(!packet.write_truncated(&mut buffer).unwrap());
+
+        // Leaving out additional records doesn't set TC
+        packet.resources = std::mem::take(&mut packet.answers);
+        let mut buffer = BytePacketBuffer::new();
+        assert!(packet.write_truncated(&mut buffer).unwrap());
+        buffer.seek(0).unwrap();
+        let parsed = DnsPacket::from_buffer(&mut buffer).unwrap();
+        assert!(!parsed.header.truncated_message);
+        assert!(parsed.resources.len() < 100);
+    }
+
+    #[test]
+    fn test_referral_bailiwick() {
+        let mut packet = DnsPacket::new();
+        packet.authorities.push(DnsRecord::NS {
+            domain: "example.com".to_string(),
+            host: "ns1.example.com".to_string(),
+            ttl: 300,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: "ns1.example.com".to_string(),
+            addr: Ipv4Addr::new(10, 0, 0, 1),
+            ttl: 300,
+        });
+        // Glue for a name the .com servers have no authority over
+        packet.resources.push(DnsRecord::A {
+            domain: "www.example.org".to_string(),
+            addr: Ipv4Addr::new(10, 0, 0, 2),
+            ttl: 300,
+        });
+
+        packet.discard_out_of_zone("com");
+        assert_eq!(packet.resources.len(), 1);
+
+        assert_eq!(
+            packet.get_delegation("www.example.com"),
+            Some("example.com")
+        );
+        assert_eq!(packet.get_resolved_ns("www.example.com").len(), 1);
+        assert!(packet.get_resolved_ns("www.evilexample.com").is_empty());
     }
 }
@@ -1,6 +1,9 @@
+use std::fmt;
+
 use crate::buffer::buffer::BytePacketBuffer;
 use crate::utils::types::Result;
 
+use super::domain_name::escape_name;
 use super::query_type::QueryType;
 
 //DnsQuestion allows adding of more records later on
@@ -35,3 +38,15 @@ impl DnsQuestion {
         Ok(())
     }
 }
+
+/// A question as dig shows it, commented out since it isn't a record
+impl fmt::Display for DnsQuestion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            ";{}\tIN\t{}",
+            escape_name(&self.name),
+            self.question_type
+        )
+    }
+}
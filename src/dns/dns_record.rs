@@ -1,8 +1,11 @@
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 use crate::buffer::buffer::BytePacketBuffer;
 use crate::utils::types::Result;
 
+use super::domain_name::escape_name;
+use super::edns_option::EdnsOption;
 use super::query_type::QueryType;
 
 //DnsRecord represents the actual dns record
@@ -12,7 +15,8 @@ pub enum DnsRecord {
     UNKNOWN {
         domain: String,
         qtype: u16,
-        data_len: u16,
+        class: u16,
+        data: Vec<u8>,
         ttl: u32,
     }, // 0
     A {
@@ -30,27 +34,215 @@ pub enum DnsRecord {
         host: String,
         ttl: u32,
     }, // 5
+    SOA {
+        domain: String,
+        mname: String,
+        rname: String,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32,
+        ttl: u32,
+    }, // 6
+    PTR {
+        domain: String,
+        host: String,
+        ttl: u32,
+    }, // 12
     MX {
         domain: String,
         priority: u16,
         host: String,
         ttl: u32,
     }, // 15
+    TXT {
+        domain: String,
+        // Character-strings are arbitrary bytes, which needn't be UTF-8
+        data: Vec<Vec<u8>>,
+        ttl: u32,
+    }, // 16
     AAAA {
         domain: String,
         addr: Ipv6Addr,
         ttl: u32,
     }, // 28
+    SRV {
+        domain: String,
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+        ttl: u32,
+    }, // 33
+    // The EDNS(0) pseudo-record of RFC 6891. Its owner is always the root and
+    // the class and TTL fields are repurposed, so they are stored decoded.
+    OPT {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        dnssec_ok: bool,
+        options: Vec<EdnsOption>,
+    }, // 41
 }
 
 impl DnsRecord {
+    /// The owner name of the record
+    pub fn domain(&self) -> &str {
+        match *self {
+            DnsRecord::UNKNOWN { ref domain, .. }
+            | DnsRecord::A { ref domain, .. }
+            | DnsRecord::NS { ref domain, .. }
+            | DnsRecord::CNAME { ref domain, .. }
+            | DnsRecord::SOA { ref domain, .. }
+            | DnsRecord::MX { ref domain, .. }
+            | DnsRecord::PTR { ref domain, .. }
+            | DnsRecord::TXT { ref domain, .. }
+            | DnsRecord::SRV { ref domain, .. }
+            | DnsRecord::AAAA { ref domain, .. } => domain,
+            DnsRecord::OPT { .. } => "",
+        }
+    }
+
+    /// The type of the record, as it would appear on the wire
+    pub fn query_type(&self) -> QueryType {
+        match *self {
+            DnsRecord::UNKNOWN { qtype, .. } => QueryType::from_num(qtype),
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::NS { .. } => QueryType::NS,
+            DnsRecord::CNAME { .. } => QueryType::CNAME,
+            DnsRecord::SOA { .. } => QueryType::SOA,
+            DnsRecord::MX { .. } => QueryType::MX,
+            DnsRecord::PTR { .. } => QueryType::PTR,
+            DnsRecord::TXT { .. } => QueryType::TXT,
+            DnsRecord::AAAA { .. } => QueryType::AAAA,
+            DnsRecord::SRV { .. } => QueryType::SRV,
+            DnsRecord::OPT { .. } => QueryType::OPT,
+        }
+    }
+
+    /// The time to live of the record in seconds
+    pub fn ttl(&self) -> u32 {
+        match *self {
+            DnsRecord::UNKNOWN { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::NS { ttl, .. }
+            | DnsRecord::CNAME { ttl, .. }
+            | DnsRecord::SOA { ttl, .. }
+            | DnsRecord::MX { ttl, .. }
+            | DnsRecord::PTR { ttl, .. }
+            | DnsRecord::TXT { ttl, .. }
+            | DnsRecord::SRV { ttl, .. }
+            | DnsRecord::AAAA { ttl, .. } => ttl,
+            // OPT records must never be cached, so they report no lifetime
+            DnsRecord::OPT { .. } => 0,
+        }
+    }
+
+    /// Overwrite the time to live, e.g. when handing out a cached copy
+    pub fn set_ttl(&mut self, new_ttl: u32) {
+        match *self {
+            DnsRecord::UNKNOWN { ref mut ttl, .. }
+            | DnsRecord::A { ref mut ttl, .. }
+            | DnsRecord::NS { ref mut ttl, .. }
+            | DnsRecord::CNAME { ref mut ttl, .. }
+            | DnsRecord::SOA { ref mut ttl, .. }
+            | DnsRecord::MX { ref mut ttl, .. }
+            | DnsRecord::PTR { ref mut ttl, .. }
+            | DnsRecord::TXT { ref mut ttl, .. }
+            | DnsRecord::SRV { ref mut ttl, .. }
+            | DnsRecord::AAAA { ref mut ttl, .. } => *ttl = new_ttl,
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    /// Overwrite the owner name, e.g. when synthesizing records from a wildcard
+    pub fn set_domain(&mut self, new_domain: &str) {
+        match *self {
+            DnsRecord::UNKNOWN { ref mut domain, .. }
+            | DnsRecord::A { ref mut domain, .. }
+            | DnsRecord::NS { ref mut domain, .. }
+            | DnsRecord::CNAME { ref mut domain, .. }
+            | DnsRecord::SOA { ref mut domain, .. }
+            | DnsRecord::MX { ref mut domain, .. }
+            | DnsRecord::PTR { ref mut domain, .. }
+            | DnsRecord::TXT { ref mut domain, .. }
+            | DnsRecord::SRV { ref mut domain, .. }
+            | DnsRecord::AAAA { ref mut domain, .. } => *domain = new_domain.to_string(),
+            DnsRecord::OPT { .. } => {}
+        }
+    }
+
+    /// The rdata of the record in presentation format, as it appears in zone
+    /// files after the type
+    pub fn rdata(&self) -> String {
+        match *self {
+            DnsRecord::UNKNOWN { .. } => self.generic_rdata().unwrap_or_default(),
+            DnsRecord::A { addr, .. } => addr.to_string(),
+            DnsRecord::AAAA { addr, .. } => addr.to_string(),
+            DnsRecord::NS { ref host, .. }
+            | DnsRecord::CNAME { ref host, .. }
+            | DnsRecord::PTR { ref host, .. } => escape_name(host),
+            DnsRecord::MX {
+                priority, ref host, ..
+            } => format!("{} {}", priority, escape_name(host)),
+            DnsRecord::SOA {
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ..
+            } => format!(
+                "{} {} {} {} {} {} {}",
+                escape_name(mname),
+                escape_name(rname),
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum
+            ),
+            DnsRecord::TXT { ref data, .. } => {
+                let strings: Vec<String> = data.iter().map(|text| quote(text)).collect();
+                strings.join(" ")
+            }
+            DnsRecord::SRV {
+                priority,
+                weight,
+                port,
+                ref target,
+                ..
+            } => format!("{} {} {} {}", priority, weight, port, escape_name(target)),
+            DnsRecord::OPT { .. } => String::new(),
+        }
+    }
+
+    /// The rdata of a record of unknown type in the generic presentation
+    /// form of RFC 3597, e.g. `\# 4 0a000001`. Known types return `None`.
+    pub fn generic_rdata(&self) -> Option<String> {
+        match *self {
+            DnsRecord::UNKNOWN { ref data, .. } => {
+                let hex: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+                if hex.is_empty() {
+                    Some("\\# 0".to_string())
+                } else {
+                    Some(format!("\\# {} {}", data.len(), hex))
+                }
+            }
+            _ => None,
+        }
+    }
+
     pub fn read(buffer: &mut BytePacketBuffer) -> Result<DnsRecord> {
         let mut domain = String::new();
         buffer.read_qname(&mut domain)?;
 
         let qtype_num = buffer.read_u16()?;
         let qtype = QueryType::from_num(qtype_num);
-        let _ = buffer.read_u16()?;
+        let class = buffer.read_u16()?;
         let ttl = buffer.read_u32()?;
         let data_len = buffer.read_u16()?;
 
@@ -61,7 +253,7 @@ impl DnsRecord {
                     ((raw_addr >> 24) & 0xFF) as u8,
                     ((raw_addr >> 16) & 0xFF) as u8,
                     ((raw_addr >> 8) & 0xFF) as u8,
-                    ((raw_addr >> 0) & 0xFF) as u8,
+                    (raw_addr & 0xFF) as u8,
                 );
 
                 Ok(DnsRecord::A { domain, addr, ttl })
@@ -71,9 +263,9 @@ impl DnsRecord {
                 buffer.read_qname(&mut ns)?;
 
                 Ok(DnsRecord::NS {
-                    domain: domain,
+                    domain,
                     host: ns,
-                    ttl: ttl,
+                    ttl,
                 })
             }
             QueryType::CNAME => {
@@ -86,6 +278,40 @@ impl DnsRecord {
                     ttl,
                 })
             }
+            QueryType::SOA => {
+                let mut mname = String::new();
+                buffer.read_qname(&mut mname)?;
+                let mut rname = String::new();
+                buffer.read_qname(&mut rname)?;
+
+                let serial = buffer.read_u32()?;
+                let refresh = buffer.read_u32()?;
+                let retry = buffer.read_u32()?;
+                let expire = buffer.read_u32()?;
+                let minimum = buffer.read_u32()?;
+
+                Ok(DnsRecord::SOA {
+                    domain,
+                    mname,
+                    rname,
+                    serial,
+                    refresh,
+                    retry,
+                    expire,
+                    minimum,
+                    ttl,
+                })
+            }
+            QueryType::PTR => {
+                let mut ptr = String::new();
+                buffer.read_qname(&mut ptr)?;
+
+                Ok(DnsRecord::PTR {
+                    domain,
+                    host: ptr,
+                    ttl,
+                })
+            }
             QueryType::MX => {
                 let priority = buffer.read_u16()?;
                 let mut mx = String::new();
@@ -98,6 +324,38 @@ impl DnsRecord {
                     ttl,
                 })
             }
+            QueryType::TXT => {
+                // The rdata is a sequence of character-strings, each prefixed
+                // by a single length byte
+                let end = buffer.pos() + data_len as usize;
+                let mut data = Vec::new();
+                while buffer.pos() < end {
+                    let len = buffer.read()? as usize;
+                    data.push(buffer.read_bytes(len)?);
+                }
+                // The last string has to end exactly where the rdata does
+                if buffer.pos() != end {
+                    return Err("TXT string runs past the end of the record".into());
+                }
+
+                Ok(DnsRecord::TXT { domain, data, ttl })
+            }
+            QueryType::SRV => {
+                let priority = buffer.read_u16()?;
+                let weight = buffer.read_u16()?;
+                let port = buffer.read_u16()?;
+                let mut target = String::new();
+                buffer.read_qname(&mut target)?;
+
+                Ok(DnsRecord::SRV {
+                    domain,
+                    priority,
+                    weight,
+                    port,
+                    target,
+                    ttl,
+                })
+            }
             QueryType::AAAA => {
                 let raw_addr1 = buffer.read_u32()?;
                 let raw_addr2 = buffer.read_u32()?;
@@ -105,28 +363,45 @@ impl DnsRecord {
                 let raw_addr4 = buffer.read_u32()?;
                 let addr = Ipv6Addr::new(
                     ((raw_addr1 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr1 >> 0) & 0xFFFF) as u16,
+                    (raw_addr1 & 0xFFFF) as u16,
                     ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr2 >> 0) & 0xFFFF) as u16,
+                    (raw_addr2 & 0xFFFF) as u16,
                     ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr3 >> 0) & 0xFFFF) as u16,
+                    (raw_addr3 & 0xFFFF) as u16,
                     ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                    ((raw_addr4 >> 0) & 0xFFFF) as u16,
+                    (raw_addr4 & 0xFFFF) as u16,
                 );
 
-                Ok(DnsRecord::AAAA {
-                    domain: domain,
-                    addr: addr,
-                    ttl: ttl,
+                Ok(DnsRecord::AAAA { domain, addr, ttl })
+            }
+            QueryType::OPT => {
+                let end = buffer.pos() + data_len as usize;
+                let mut options = Vec::new();
+                while buffer.pos() < end {
+                    options.push(EdnsOption::read(buffer)?);
+                }
+                if buffer.pos() != end {
+                    return Err("EDNS option runs past the end of the OPT record".into());
+                }
+
+                Ok(DnsRecord::OPT {
+                    udp_payload_size: class,
+                    extended_rcode: (ttl >> 24) as u8,
+                    version: (ttl >> 16) as u8,
+                    dnssec_ok: (ttl & 0x8000) > 0,
+                    options,
                 })
             }
             QueryType::UNKNOWN(_) => {
-                buffer.step(data_len as usize)?;
+                // We don't know how to interpret the rdata, but keep it around
+                // so that it can be handed on to clients unchanged (RFC 3597)
+                let data = buffer.read_bytes(data_len as usize)?;
 
                 Ok(DnsRecord::UNKNOWN {
                     domain,
                     qtype: qtype_num,
-                    data_len,
+                    class,
+                    data,
                     ttl,
                 })
             }
@@ -212,6 +487,36 @@ impl DnsRecord {
                 let size = buffer.pos() - (pos + 2);
                 buffer.set_u16(pos, size as u16)?;
             }
+            DnsRecord::SOA {
+                ref domain,
+                ref mname,
+                ref rname,
+                serial,
+                refresh,
+                retry,
+                expire,
+                minimum,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SOA.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(mname)?;
+                buffer.write_qname(rname)?;
+                buffer.write_u32(serial)?;
+                buffer.write_u32(refresh)?;
+                buffer.write_u32(retry)?;
+                buffer.write_u32(expire)?;
+                buffer.write_u32(minimum)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
             DnsRecord::AAAA {
                 ref domain,
                 ref addr,
@@ -227,10 +532,364 @@ impl DnsRecord {
                     buffer.write_u16(*octet)?;
                 }
             }
-            DnsRecord::UNKNOWN { .. } => {
-                println!("skipping unknown record : {:?}", self);
+            DnsRecord::PTR {
+                ref domain,
+                ref host,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::PTR.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_qname(host)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::TXT {
+                ref domain,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::TXT.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                // A character-string holds at most 255 bytes, so longer
+                // strings are split over several. An empty record still
+                // needs a single empty string.
+                if data.is_empty() {
+                    buffer.write_u8(0)?;
+                }
+                for bytes in data {
+                    if bytes.is_empty() {
+                        buffer.write_u8(0)?;
+                    }
+                    for chunk in bytes.chunks(255) {
+                        buffer.write_u8(chunk.len() as u8)?;
+                        buffer.write_bytes(chunk)?;
+                    }
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::SRV {
+                ref domain,
+                priority,
+                weight,
+                port,
+                ref target,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(QueryType::SRV.to_num())?;
+                buffer.write_u16(1)?;
+                buffer.write_u32(ttl)?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                buffer.write_u16(priority)?;
+                buffer.write_u16(weight)?;
+                buffer.write_u16(port)?;
+                // SRV postdates RFC 1035, so its target must not be compressed
+                buffer.write_qname_uncompressed(target)?;
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::OPT {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                dnssec_ok,
+                ref options,
+            } => {
+                buffer.write_qname("")?;
+                buffer.write_u16(QueryType::OPT.to_num())?;
+                buffer.write_u16(udp_payload_size)?;
+                buffer.write_u32(
+                    (extended_rcode as u32) << 24
+                        | (version as u32) << 16
+                        | (dnssec_ok as u32) << 15,
+                )?;
+
+                let pos = buffer.pos();
+                buffer.write_u16(0)?;
+
+                for option in options {
+                    option.write(buffer)?;
+                }
+
+                let size = buffer.pos() - (pos + 2);
+                buffer.set_u16(pos, size as u16)?;
+            }
+            DnsRecord::UNKNOWN {
+                ref domain,
+                qtype,
+                class,
+                ref data,
+                ttl,
+            } => {
+                buffer.write_qname(domain)?;
+                buffer.write_u16(qtype)?;
+                buffer.write_u16(class)?;
+                buffer.write_u32(ttl)?;
+                buffer.write_u16(data.len() as u16)?;
+                buffer.write_bytes(data)?;
             }
         }
         Ok(buffer.pos() - start_pos)
     }
 }
+
+/// A record in the master file format of RFC 1035, `owner TTL class type
+/// rdata`, with absolute names. The OPT pseudo-record isn't a record of any
+/// zone, so it's written the way dig shows it instead, as a comment.
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let DnsRecord::OPT {
+            udp_payload_size,
+            extended_rcode,
+            version,
+            dnssec_ok,
+            ref options,
+        } = *self
+        {
+            let flags = if dnssec_ok { " do" } else { "" };
+            write!(
+                f,
+                "; EDNS: version: {}, flags:{}; udp: {}",
+                version, flags, udp_payload_size
+            )?;
+            if extended_rcode > 0 {
+                write!(f, "; extended rcode: {}", extended_rcode)?;
+            }
+            for option in options {
+                write!(f, "\n; {}", option)?;
+            }
+            return Ok(());
+        }
+
+        let class = match *self {
+            DnsRecord::UNKNOWN { class, .. } if class != 1 => format!("CLASS{}", class),
+            _ => "IN".to_string(),
+        };
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t{}",
+            escape_name(self.domain()),
+            self.ttl(),
+            class,
+            self.query_type(),
+            self.rdata()
+        )
+    }
+}
+
+// Quote a character-string, escaping quotes and backslashes, and writing
+// bytes that aren't printable ASCII as `\DDD`
+fn quote(text: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &byte in text {
+        match byte {
+            b'"' | b'\\' => {
+                quoted.push('\\');
+                quoted.push(byte as char);
+            }
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(record: &DnsRecord) -> DnsRecord {
+        let mut buffer = BytePacketBuffer::new();
+        record.write(&mut buffer).unwrap();
+        buffer.seek(0).unwrap();
+        DnsRecord::read(&mut buffer).unwrap()
+    }
+
+    #[test]
+    fn test_soa_round_trip() {
+        let record = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 2024010101,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn test_txt_round_trip() {
+        let record = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![b"v=spf1 -all".to_vec(), Vec::new()],
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&record), record);
+
+        // Strings needn't be UTF-8, and come back byte for byte
+        let record = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![vec![0x80, 0xff, 0x00, b'a', 0xc3]],
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn test_txt_length_overrun() {
+        // A root owner, type TXT, class IN and TTL, then an rdlength of 3
+        // holding a string that claims to be 5 bytes long
+        let mut raw = vec![0, 0, 16, 0, 1, 0, 0, 1, 44, 0, 3];
+        raw.extend_from_slice(&[5, b'a', b'b', b'c', b'd', b'e']);
+
+        let mut buffer = BytePacketBuffer::from_slice(&raw);
+        assert!(DnsRecord::read(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_long_txt_is_split() {
+        let long = vec![b'a'; 300];
+        let record = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![long],
+            ttl: 300,
+        };
+        match round_trip(&record) {
+            DnsRecord::TXT { data, .. } => {
+                assert_eq!(data.len(), 2);
+                assert_eq!(data[0].len(), 255);
+                assert_eq!(data[1].len(), 45);
+            }
+            other => panic!("unexpected record {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ptr_and_srv_round_trip() {
+        let ptr = DnsRecord::PTR {
+            domain: "4.3.2.1.in-addr.arpa".to_string(),
+            host: "www.example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&ptr), ptr);
+
+        let srv = DnsRecord::SRV {
+            domain: "_sip._tcp.example.com".to_string(),
+            priority: 10,
+            weight: 60,
+            port: 5060,
+            target: "sip.example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&srv), srv);
+    }
+
+    #[test]
+    fn test_unknown_round_trip() {
+        let record = DnsRecord::UNKNOWN {
+            domain: "example.com".to_string(),
+            qtype: 99,
+            class: 1,
+            data: vec![0x0a, 0x00, 0x00, 0x01],
+            ttl: 300,
+        };
+        assert_eq!(round_trip(&record), record);
+        assert_eq!(record.generic_rdata().unwrap(), "\\# 4 0a000001");
+    }
+
+    #[test]
+    fn test_opt_round_trip() {
+        let record = DnsRecord::OPT {
+            udp_payload_size: 4096,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: true,
+            options: vec![
+                EdnsOption::COOKIE {
+                    client: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                    server: vec![],
+                },
+                EdnsOption::EDE {
+                    info_code: 22,
+                    extra_text: "no reachable authority".to_string(),
+                },
+            ],
+        };
+        assert_eq!(round_trip(&record), record);
+    }
+
+    #[test]
+    fn test_opt_length_overrun() {
+        // A root owner, type OPT, a payload size of 4096 and no flags, then an
+        // rdlength of 4 holding an option that claims to be 8 bytes long
+        let mut raw = vec![0, 0, 41, 16, 0, 0, 0, 0, 0, 0, 4];
+        raw.extend_from_slice(&[0, 10, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let mut buffer = BytePacketBuffer::from_slice(&raw);
+        assert!(DnsRecord::read(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let mx = DnsRecord::MX {
+            domain: "example.com".to_string(),
+            priority: 10,
+            host: "mail.example.com".to_string(),
+            ttl: 300,
+        };
+        assert_eq!(
+            mx.to_string(),
+            "example.com.\t300\tIN\tMX\t10 mail.example.com."
+        );
+
+        let txt = DnsRecord::TXT {
+            domain: "example.com".to_string(),
+            data: vec![
+                b"say \"hi\"".to_vec(),
+                b"\\".to_vec(),
+                vec![0xc3, 0x28, 0xff],
+            ],
+            ttl: 300,
+        };
+        assert_eq!(
+            txt.to_string(),
+            "example.com.\t300\tIN\tTXT\t\"say \\\"hi\\\"\" \"\\\\\" \"\\195(\\255\""
+        );
+
+        let unknown = DnsRecord::UNKNOWN {
+            domain: "example.com".to_string(),
+            qtype: 65534,
+            class: 1,
+            data: vec![10, 0, 0, 1],
+            ttl: 300,
+        };
+        assert_eq!(
+            unknown.to_string(),
+            "example.com.\t300\tIN\tTYPE65534\t\\# 4 0a000001"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+/// Check whether `name` is `zone` itself or a name below it. The comparison
+/// is made on whole labels and ignores case, so `www.example.com` is within
+/// `example.com` while `www.evilexample.com` isn't. Every name is within the
+/// root zone, which is the empty name.
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let name = name.trim_end_matches('.').as_bytes();
+    let zone = zone.trim_end_matches('.').as_bytes();
+
+    if zone.is_empty() {
+        return true;
+    }
+    if name.len() < zone.len() {
+        return false;
+    }
+
+    let (head, tail) = name.split_at(name.len() - zone.len());
+    tail.eq_ignore_ascii_case(zone) && (head.is_empty() || head.ends_with(b"."))
+}
+
+/// Write a name the way it appears in zone files: absolute, with a trailing
+/// dot, and with characters that have a meaning there escaped (RFC 1035,
+/// section 5.1). The root is written as a lone dot.
+pub fn escape_name(name: &str) -> String {
+    let mut escaped = String::new();
+
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        for &byte in label.as_bytes() {
+            match byte {
+                b'"' | b'(' | b')' | b';' | b'\\' | b'@' | b'$' => {
+                    escaped.push('\\');
+                    escaped.push(byte as char);
+                }
+                0x21..=0x7e => escaped.push(byte as char),
+                _ => escaped.push_str(&format!("\\{:03}", byte)),
+            }
+        }
+        escaped.push('.');
+    }
+
+    if escaped.is_empty() {
+        escaped.push('.');
+    }
+    escaped
+}
+
+/// Check whether `name` is strictly below `zone`
+pub fn is_strict_subdomain(name: &str, zone: &str) -> bool {
+    is_subdomain(name, zone) && !is_subdomain(zone, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_subdomain() {
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("example.com", "EXAMPLE.com."));
+        assert!(is_subdomain("example.com", ""));
+        assert!(!is_subdomain("www.evilexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+        assert!(!is_subdomain("", "com"));
+    }
+
+    #[test]
+    fn test_escape_name() {
+        assert_eq!(escape_name("www.example.com"), "www.example.com.");
+        assert_eq!(escape_name(""), ".");
+        assert_eq!(escape_name("a b;c.example"), "a\\032b\\;c.example.");
+    }
+
+    #[test]
+    fn test_is_strict_subdomain() {
+        assert!(is_strict_subdomain("example.com", "com"));
+        assert!(is_strict_subdomain("com", ""));
+        assert!(!is_strict_subdomain("com", "com"));
+        assert!(!is_strict_subdomain("", ""));
+    }
+}
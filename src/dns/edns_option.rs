@@ -0,0 +1,115 @@
+use std::fmt;
+
+use crate::buffer::buffer::BytePacketBuffer;
+use crate::utils::types::Result;
+
+//EdnsOption represents a single option carried in the rdata of an OPT record
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum EdnsOption {
+    UNKNOWN { code: u16, data: Vec<u8> },        // 0
+    NSID { data: Vec<u8> },                      // 3
+    COOKIE { client: Vec<u8>, server: Vec<u8> }, // 10
+    PADDING { len: u16 },                        // 12
+    EDE { info_code: u16, extra_text: String },  // 15
+}
+
+impl EdnsOption {
+    pub fn code(&self) -> u16 {
+        match *self {
+            EdnsOption::UNKNOWN { code, .. } => code,
+            EdnsOption::NSID { .. } => 3,
+            EdnsOption::COOKIE { .. } => 10,
+            EdnsOption::PADDING { .. } => 12,
+            EdnsOption::EDE { .. } => 15,
+        }
+    }
+
+    // read a single option, its code and length included, from the buffer
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<EdnsOption> {
+        let code = buffer.read_u16()?;
+        let len = buffer.read_u16()? as usize;
+        let mut data = buffer.read_bytes(len)?;
+
+        match code {
+            3 => Ok(EdnsOption::NSID { data }),
+            // The client cookie is always 8 bytes, the server cookie is
+            // whatever follows it, if anything
+            10 if len >= 8 => {
+                let server = data.split_off(8);
+                Ok(EdnsOption::COOKIE {
+                    client: data,
+                    server,
+                })
+            }
+            12 => Ok(EdnsOption::PADDING { len: len as u16 }),
+            15 if len >= 2 => {
+                let info_code = (data[0] as u16) << 8 | data[1] as u16;
+                let extra_text = String::from_utf8_lossy(&data[2..]).into_owned();
+                Ok(EdnsOption::EDE {
+                    info_code,
+                    extra_text,
+                })
+            }
+            _ => Ok(EdnsOption::UNKNOWN { code, data }),
+        }
+    }
+
+    // write the option, its code and length included, to the buffer
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<()> {
+        buffer.write_u16(self.code())?;
+
+        match *self {
+            EdnsOption::UNKNOWN { ref data, .. } | EdnsOption::NSID { ref data } => {
+                buffer.write_u16(data.len() as u16)?;
+                buffer.write_bytes(data)?;
+            }
+            EdnsOption::COOKIE {
+                ref client,
+                ref server,
+            } => {
+                buffer.write_u16((client.len() + server.len()) as u16)?;
+                buffer.write_bytes(client)?;
+                buffer.write_bytes(server)?;
+            }
+            EdnsOption::PADDING { len } => {
+                buffer.write_u16(len)?;
+                for _ in 0..len {
+                    buffer.write_u8(0)?;
+                }
+            }
+            EdnsOption::EDE {
+                info_code,
+                ref extra_text,
+            } => {
+                buffer.write_u16(2 + extra_text.len() as u16)?;
+                buffer.write_u16(info_code)?;
+                buffer.write_bytes(extra_text.as_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// An option the way dig shows it in the OPT pseudosection
+impl fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EdnsOption::UNKNOWN { code, ref data } => write!(f, "OPT={}: {}", code, hex(data)),
+            EdnsOption::NSID { ref data } => write!(f, "NSID: {}", hex(data)),
+            EdnsOption::COOKIE {
+                ref client,
+                ref server,
+            } => write!(f, "COOKIE: {}{}", hex(client), hex(server)),
+            EdnsOption::PADDING { len } => write!(f, "PADDING: {} bytes", len),
+            EdnsOption::EDE {
+                info_code,
+                ref extra_text,
+            } => write!(f, "EDE: {} ({})", info_code, extra_text),
+        }
+    }
+}
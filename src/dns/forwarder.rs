@@ -0,0 +1,246 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::utils::types::Result;
+use crate::{log_debug, log_warn};
+
+use super::dns_cache::DnsCache;
+use super::dns_header::ResultCode;
+use super::dns_lookup::{lookup, QueryOptions, ResolverConfig};
+use super::dns_packet::DnsPacket;
+use super::query_type::QueryType;
+
+/// How many failures in a row it takes for an upstream to be considered down
+const MAX_FAILURES: u32 = 3;
+
+/// How long an upstream that is down is only used as a last resort
+const DOWN_TIME: Duration = Duration::from_secs(30);
+
+/// How an upstream is picked from the ones that are up
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// Take turns, spreading queries evenly over the upstreams
+    RoundRobin,
+    /// Prefer the upstream that has been answering the fastest
+    LowestLatency,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    down_until: Option<Instant>,
+    // Smoothed round trip time, the way TCP computes it (RFC 6298)
+    latency: Option<Duration>,
+}
+
+struct Upstream {
+    addr: SocketAddr,
+    health: Mutex<Health>,
+}
+
+impl Upstream {
+    fn is_up(&self, now: Instant) -> bool {
+        match self.health.lock() {
+            Ok(health) => health.down_until.is_none_or(|until| now >= until),
+            Err(_) => true,
+        }
+    }
+
+    fn latency(&self) -> Duration {
+        self.health
+            .lock()
+            .ok()
+            .and_then(|health| health.latency)
+            .unwrap_or_default()
+    }
+
+    fn record_success(&self, rtt: Duration) {
+        if let Ok(mut health) = self.health.lock() {
+            health.failures = 0;
+            health.down_until = None;
+            health.latency = Some(match health.latency {
+                Some(latency) => latency * 7 / 8 + rtt / 8,
+                None => rtt,
+            });
+        }
+    }
+
+    fn record_failure(&self) {
+        if let Ok(mut health) = self.health.lock() {
+            health.failures += 1;
+            if health.failures >= MAX_FAILURES {
+                log_warn!("upstream {} is down", self.addr);
+                health.down_until = Some(Instant::now() + DOWN_TIME);
+            }
+        }
+    }
+}
+
+/// Forwarder sends queries on to other resolvers, which do the recursion for
+/// us, instead of walking the tree from the root ourselves
+pub struct Forwarder {
+    upstreams: Vec<Upstream>,
+    selection: Selection,
+    next: AtomicUsize,
+    /// Whether to resolve questions ourselves when no upstream answers them
+    pub fallback_to_recursion: bool,
+}
+
+impl Forwarder {
+    pub fn new(upstreams: Vec<SocketAddr>, selection: Selection) -> Forwarder {
+        Forwarder {
+            upstreams: upstreams
+                .into_iter()
+                .map(|addr| Upstream {
+                    addr,
+                    health: Mutex::new(Health::default()),
+                })
+                .collect(),
+            selection,
+            next: AtomicUsize::new(0),
+            fallback_to_recursion: true,
+        }
+    }
+
+    /// The upstreams in the order they should be tried for the next query.
+    /// Those that are down come last, so they are still used when every
+    /// upstream is down.
+    fn order(&self) -> Vec<&Upstream> {
+        let now = Instant::now();
+        let mut upstreams: Vec<&Upstream> = self.upstreams.iter().collect();
+
+        match self.selection {
+            Selection::RoundRobin => {
+                if !upstreams.is_empty() {
+                    let next = self.next.fetch_add(1, Ordering::Relaxed);
+                    upstreams.rotate_left(next % self.upstreams.len());
+                }
+            }
+            // Upstreams we haven't heard from yet have no latency, so they
+            // go first and get measured
+            Selection::LowestLatency => upstreams.sort_by_key(|upstream| upstream.latency()),
+        }
+        upstreams.sort_by_key(|upstream| !upstream.is_up(now));
+
+        upstreams
+    }
+
+    /// Answer a question from the cache, or else from the first upstream
+    /// that answers it. The upstreams are recursive resolvers, so unlike in
+    /// `recursive_lookup` the query has the RD flag set and we don't look
+    /// any further than the answer we get.
+    pub fn lookup(
+        &self,
+        qname: &str,
+        qtype: QueryType,
+        cache: &DnsCache,
+        config: &ResolverConfig,
+    ) -> Result<DnsPacket> {
+        if let Some(cached) = cache.lookup(qname, qtype) {
+            log_debug!("cache hit for {:?} {}", qtype, qname);
+            return Ok(cached);
+        }
+
+        let deadline = Instant::now() + config.resolution_timeout;
+        let mut last_error = None;
+
+        for _ in 0..=config.retries {
+            for upstream in self.order() {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(format!("Timed out forwarding {:?} {}", qtype, qname).into());
+                }
+                let timeout = config.query_timeout.min(deadline - now);
+
+                log_debug!("forwarding {:?} {} to {}", qtype, qname, upstream.addr);
+
+                match lookup(qname, qtype, upstream.addr, &QueryOptions::new(timeout)) {
+                    Ok(response)
+                        if matches!(
+                            response.header.rescode,
+                            ResultCode::SERVFAIL | ResultCode::REFUSED
+                        ) =>
+                    {
+                        upstream.record_failure();
+                        last_error = Some(
+                            format!("{:?} from {}", response.header.rescode, upstream.addr).into(),
+                        );
+                    }
+                    Ok(response) => {
+                        upstream.record_success(now.elapsed());
+
+                        // Only what answers the question is cached. The rest of
+                        // the response isn't ours to check, since we don't know
+                        // which zones the upstream got it from.
+                        cache.store(&response.answers);
+                        cache.store_negative(qname, qtype, &response);
+
+                        return Ok(response);
+                    }
+                    Err(e) => {
+                        log_debug!("forwarding to {} failed: {}", upstream.addr, e);
+                        upstream.record_failure();
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| "No upstreams to forward to".into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(forwarder: &Forwarder) -> Vec<SocketAddr> {
+        forwarder
+            .order()
+            .iter()
+            .map(|upstream| upstream.addr)
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let a: SocketAddr = "192.0.2.1:53".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:53".parse().unwrap();
+        let forwarder = Forwarder::new(vec![a, b], Selection::RoundRobin);
+
+        assert_eq!(addrs(&forwarder), vec![a, b]);
+        assert_eq!(addrs(&forwarder), vec![b, a]);
+        assert_eq!(addrs(&forwarder), vec![a, b]);
+    }
+
+    #[test]
+    fn test_lowest_latency() {
+        let a: SocketAddr = "192.0.2.1:53".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:53".parse().unwrap();
+        let forwarder = Forwarder::new(vec![a, b], Selection::LowestLatency);
+
+        forwarder.upstreams[0].record_success(Duration::from_millis(80));
+        forwarder.upstreams[1].record_success(Duration::from_millis(20));
+        assert_eq!(addrs(&forwarder), vec![b, a]);
+    }
+
+    #[test]
+    fn test_down_upstreams_go_last() {
+        let a: SocketAddr = "192.0.2.1:53".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:53".parse().unwrap();
+        let forwarder = Forwarder::new(vec![a, b], Selection::LowestLatency);
+
+        forwarder.upstreams[0].record_success(Duration::from_millis(10));
+        forwarder.upstreams[1].record_success(Duration::from_millis(20));
+        for _ in 0..MAX_FAILURES {
+            forwarder.upstreams[0].record_failure();
+        }
+        assert_eq!(addrs(&forwarder), vec![b, a]);
+
+        // A single success brings it back
+        forwarder.upstreams[0].record_success(Duration::from_millis(10));
+        assert_eq!(addrs(&forwarder), vec![a, b]);
+    }
+}
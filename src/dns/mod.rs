@@ -1,6 +1,11 @@
+pub mod dns_cache;
 pub mod dns_header;
 pub mod dns_lookup;
 pub mod dns_packet;
 pub mod dns_question;
 pub mod dns_record;
+pub mod domain_name;
+pub mod edns_option;
+pub mod forwarder;
 pub mod query_type;
+pub mod root_hints;
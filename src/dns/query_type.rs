@@ -1,3 +1,6 @@
+use std::fmt;
+use std::str::FromStr;
+
 //QueryType to represent the record type being queried
 #[derive(PartialEq, Eq, Debug, Clone, Hash, Copy)]
 pub enum QueryType {
@@ -5,8 +8,13 @@ pub enum QueryType {
     A,     //1
     NS,    //2
     CNAME, //5
+    SOA,   //6
+    PTR,   //12
     MX,    //15
+    TXT,   //16
     AAAA,  //28
+    SRV,   //33
+    OPT,   //41
 }
 
 impl QueryType {
@@ -16,8 +24,13 @@ impl QueryType {
             QueryType::A => 1,
             QueryType::NS => 2,
             QueryType::CNAME => 5,
+            QueryType::SOA => 6,
+            QueryType::PTR => 12,
             QueryType::MX => 15,
+            QueryType::TXT => 16,
             QueryType::AAAA => 28,
+            QueryType::SRV => 33,
+            QueryType::OPT => 41,
         }
     }
 
@@ -26,9 +39,76 @@ impl QueryType {
             1 => QueryType::A,
             2 => QueryType::NS,
             5 => QueryType::CNAME,
+            6 => QueryType::SOA,
+            12 => QueryType::PTR,
             15 => QueryType::MX,
+            16 => QueryType::TXT,
             28 => QueryType::AAAA,
+            33 => QueryType::SRV,
+            41 => QueryType::OPT,
             _ => QueryType::UNKNOWN(num),
         }
     }
 }
+
+/// The mnemonic of the type as used in zone files, or the generic `TYPE<n>`
+/// form of RFC 3597 for types we don't know
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QueryType::UNKNOWN(num) => write!(f, "TYPE{}", num),
+            QueryType::A => write!(f, "A"),
+            QueryType::NS => write!(f, "NS"),
+            QueryType::CNAME => write!(f, "CNAME"),
+            QueryType::SOA => write!(f, "SOA"),
+            QueryType::PTR => write!(f, "PTR"),
+            QueryType::MX => write!(f, "MX"),
+            QueryType::TXT => write!(f, "TXT"),
+            QueryType::AAAA => write!(f, "AAAA"),
+            QueryType::SRV => write!(f, "SRV"),
+            QueryType::OPT => write!(f, "OPT"),
+        }
+    }
+}
+
+/// The inverse of `Display`, accepting mnemonics in any case
+impl FromStr for QueryType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<QueryType, String> {
+        let upper = s.to_uppercase();
+        let qtype = match upper.as_str() {
+            "A" => QueryType::A,
+            "NS" => QueryType::NS,
+            "CNAME" => QueryType::CNAME,
+            "SOA" => QueryType::SOA,
+            "PTR" => QueryType::PTR,
+            "MX" => QueryType::MX,
+            "TXT" => QueryType::TXT,
+            "AAAA" => QueryType::AAAA,
+            "SRV" => QueryType::SRV,
+            "OPT" => QueryType::OPT,
+            _ => upper
+                .strip_prefix("TYPE")
+                .and_then(|num| num.parse().ok())
+                .map(QueryType::from_num)
+                .ok_or_else(|| format!("unknown record type {:?}", s))?,
+        };
+        Ok(qtype)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trip() {
+        for qtype in [QueryType::A, QueryType::SRV, QueryType::UNKNOWN(65534)] {
+            assert_eq!(qtype.to_string().parse::<QueryType>(), Ok(qtype));
+        }
+        assert_eq!("mx".parse::<QueryType>(), Ok(QueryType::MX));
+        assert_eq!("TYPE28".parse::<QueryType>(), Ok(QueryType::AAAA));
+        assert!("bogus".parse::<QueryType>().is_err());
+    }
+}
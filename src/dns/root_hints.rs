@@ -0,0 +1,211 @@
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::utils::types::Result;
+
+use super::dns_record::DnsRecord;
+
+/// The TTL used for the built-in hints, matching the one in `named.root`
+const HINTS_TTL: u32 = 3600000;
+
+/// The root servers as published by IANA, as (name, IPv4, IPv6)
+const BUILTIN_ROOT_SERVERS: [(&str, &str, &str); 13] = [
+    ("a.root-servers.net", "198.41.0.4", "2001:503:ba3e::2:30"),
+    ("b.root-servers.net", "170.247.170.2", "2801:1b8:10::b"),
+    ("c.root-servers.net", "192.33.4.12", "2001:500:2::c"),
+    ("d.root-servers.net", "199.7.91.13", "2001:500:2d::d"),
+    ("e.root-servers.net", "192.203.230.10", "2001:500:a8::e"),
+    ("f.root-servers.net", "192.5.5.241", "2001:500:2f::f"),
+    ("g.root-servers.net", "192.112.36.4", "2001:500:12::d0d"),
+    ("h.root-servers.net", "198.97.190.53", "2001:500:1::53"),
+    ("i.root-servers.net", "192.36.148.17", "2001:7fe::53"),
+    ("j.root-servers.net", "192.58.128.30", "2001:503:c27::2:30"),
+    ("k.root-servers.net", "193.0.14.129", "2001:7fd::1"),
+    ("l.root-servers.net", "199.7.83.42", "2001:500:9f::42"),
+    ("m.root-servers.net", "202.12.27.33", "2001:dc3::35"),
+];
+
+/// RootHints holds the NS records of the root zone along with the addresses
+/// of those servers. It's where resolution starts when nothing closer to the
+/// name being resolved is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RootHints {
+    pub records: Vec<DnsRecord>,
+}
+
+impl Default for RootHints {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl RootHints {
+    /// The hints compiled into the binary
+    pub fn builtin() -> RootHints {
+        let mut records = Vec::new();
+
+        for (name, v4, v6) in BUILTIN_ROOT_SERVERS {
+            records.push(DnsRecord::NS {
+                domain: String::new(),
+                host: name.to_string(),
+                ttl: HINTS_TTL,
+            });
+            records.push(DnsRecord::A {
+                domain: name.to_string(),
+                addr: v4.parse().unwrap(),
+                ttl: HINTS_TTL,
+            });
+            records.push(DnsRecord::AAAA {
+                domain: name.to_string(),
+                addr: v6.parse().unwrap(),
+                ttl: HINTS_TTL,
+            });
+        }
+
+        RootHints { records }
+    }
+
+    /// Load hints from a file in the format of the `named.root` file
+    /// published by IANA
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RootHints> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("{}: {}", path.as_ref().display(), e))?;
+        Self::parse(&text)
+    }
+
+    /// Parse hints in the format of `named.root`, which is a zone file that
+    /// only holds NS records for the root and the addresses of those servers:
+    ///
+    /// ```text
+    /// .                        3600000      NS    A.ROOT-SERVERS.NET.
+    /// A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+    /// A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+    /// ```
+    pub fn parse(text: &str) -> Result<RootHints> {
+        let mut records = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            // Everything after a semicolon is a comment
+            let line = line.split(';').next().unwrap_or("");
+            let mut fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+
+            let error = |msg: &str| format!("line {}: {}", number + 1, msg);
+
+            let domain = normalize_name(fields.remove(0));
+
+            // The TTL and class are both optional
+            let mut ttl = HINTS_TTL;
+            if let Some(parsed) = fields.first().and_then(|field| field.parse().ok()) {
+                ttl = parsed;
+                fields.remove(0);
+            }
+            if fields.first().map(|field| field.eq_ignore_ascii_case("IN")) == Some(true) {
+                fields.remove(0);
+            }
+
+            let (rtype, rdata) = match fields.as_slice() {
+                [rtype, rdata] => (rtype.to_uppercase(), *rdata),
+                _ => return Err(error("expected a record type and its data").into()),
+            };
+
+            let record = match rtype.as_str() {
+                "NS" => DnsRecord::NS {
+                    domain,
+                    host: normalize_name(rdata),
+                    ttl,
+                },
+                "A" => DnsRecord::A {
+                    domain,
+                    addr: rdata.parse().map_err(|_| error("invalid IPv4 address"))?,
+                    ttl,
+                },
+                "AAAA" => DnsRecord::AAAA {
+                    domain,
+                    addr: rdata.parse().map_err(|_| error("invalid IPv6 address"))?,
+                    ttl,
+                },
+                _ => return Err(error("only NS, A and AAAA records are allowed").into()),
+            };
+            records.push(record);
+        }
+
+        let hints = RootHints { records };
+        if hints.addresses().is_empty() {
+            return Err("Root hints don't contain any root server addresses".into());
+        }
+
+        Ok(hints)
+    }
+
+    /// The addresses of all the root servers named in NS records for the root
+    pub fn addresses(&self) -> Vec<IpAddr> {
+        let hosts: Vec<&str> = self
+            .records
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::NS { domain, host, .. } if domain.is_empty() => Some(host.as_str()),
+                _ => None,
+            })
+            .collect();
+
+        self.records
+            .iter()
+            .filter(|record| hosts.contains(&record.domain()))
+            .filter_map(|record| match record {
+                DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+                DnsRecord::AAAA { addr, .. } => Some(IpAddr::V6(*addr)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// Names are stored lowercase and without the trailing dot, the way
+// `read_qname` returns them, so `.` becomes the empty root name
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_hints() {
+        let hints = RootHints::builtin();
+        assert_eq!(hints.addresses().len(), 26);
+    }
+
+    #[test]
+    fn test_parse_named_root() {
+        let text = "
+;       This file holds the information on root name servers
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+; OPERATED BY UNKNOWN
+B.ROOT-SERVERS.NET.      3600000  IN  A     170.247.170.2
+";
+        let hints = RootHints::parse(text).unwrap();
+        assert_eq!(hints.records.len(), 4);
+        // B isn't named in an NS record, so it isn't used
+        assert_eq!(
+            hints.addresses(),
+            vec![
+                "198.41.0.4".parse::<IpAddr>().unwrap(),
+                "2001:503:ba3e::2:30".parse::<IpAddr>().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(RootHints::parse(". 3600000 NS").is_err());
+        assert!(RootHints::parse("a.root-servers.net. A 300.1.1.1").is_err());
+        assert!(RootHints::parse("; nothing here").is_err());
+    }
+}
@@ -0,0 +1,5 @@
+pub mod buffer;
+pub mod dns;
+pub mod server;
+pub mod utils;
+pub mod zone;
@@ -1,96 +1,180 @@
-use std::net::UdpSocket;
-
-pub mod buffer;
-pub mod dns;
-pub mod utils;
-
-use buffer::buffer::BytePacketBuffer;
-use dns::dns_header::ResultCode;
-use dns::dns_lookup::recursive_lookup;
-use dns::dns_packet::DnsPacket;
-use utils::types::Result;
-
-/// Handle a single incoming packet
-fn handle_query(socket: &UdpSocket) -> Result<()> {
-    // With a socket ready, we can go ahead and read a packet. This will
-    // block until one is received.
-    let mut req_buffer = BytePacketBuffer::new();
-
-    // The `recv_from` function will write the data into the provided buffer,
-    // and return the length of the data read as well as the source address.
-    // We're not interested in the length, but we need to keep track of the
-    // source in order to send our reply later on.
-    let (_, src) = socket.recv_from(&mut req_buffer.buf)?;
-
-    // Next, `DnsPacket::from_buffer` is used to parse the raw bytes into
-    // a `DnsPacket`.
-    let mut request = DnsPacket::from_buffer(&mut req_buffer)?;
-
-    // Create and initialize the response packet
-    let mut packet = DnsPacket::new();
-    packet.header.id = request.header.id;
-    packet.header.recursion_desired = true;
-    packet.header.recursion_available = true;
-    packet.header.response = true;
-
-    // In the normal case, exactly one question is present
-    if let Some(question) = request.questions.pop() {
-        println!("Received query: {:?}", question);
-
-        // Since all is set up and as expected, the query can be forwarded to the
-        // target server. There's always the possibility that the query will
-        // fail, in which case the `SERVFAIL` response code is set to indicate
-        // as much to the client. If rather everything goes as planned, the
-        // question and response records as copied into our response packet.
-        if let Ok(result) = recursive_lookup(&question.name, question.question_type) {
-            packet.questions.push(question);
-            packet.header.rescode = result.header.rescode;
-
-            for rec in result.answers {
-                println!("Answer: {:?}", rec);
-                packet.answers.push(rec);
+use std::env;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dns::dns::dns_cache::DnsCache;
+use dns::dns::dns_lookup::prime_root_servers;
+use dns::dns::forwarder::Forwarder;
+use dns::dns::root_hints::RootHints;
+use dns::server::config::{Config, Mode, USAGE};
+use dns::server::context::ServerContext;
+use dns::server::tcp::serve_tcp;
+use dns::server::udp::serve_udp;
+use dns::utils::log::set_level;
+use dns::utils::thread_pool::ThreadPool;
+use dns::utils::types::Result;
+use dns::zone::authority::{Authority, Zone};
+use dns::{log_debug, log_info, log_warn};
+
+/// Root hints in the format of IANA's `named.root`. When the file is absent,
+/// the hints built into the binary are used.
+const ROOT_HINTS_FILE: &str = "named.root";
+
+/// How long to wait before trying again when priming fails
+const PRIMING_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// Keep the cached list of root servers current by priming at startup, and
+// again whenever the root NS records are about to expire
+fn keep_root_servers_primed(context: Arc<ServerContext>) {
+    loop {
+        let wait = match prime_root_servers(&context.cache, &context.resolver) {
+            Ok(ttl) => {
+                log_info!("primed root servers, next priming in {}s", ttl);
+                Duration::from_secs(ttl as u64).max(PRIMING_RETRY_INTERVAL)
+            }
+            Err(e) => {
+                log_warn!("Failed to prime root servers: {}", e);
+                PRIMING_RETRY_INTERVAL
             }
-            for rec in result.authorities {
-                println!("Authority: {:?}", rec);
-                packet.authorities.push(rec);
+        };
+        thread::sleep(wait);
+    }
+}
+
+// A socket on [::] takes IPv4 clients as well on systems such as Linux, so
+// 0.0.0.0 on the same port is expected to be in use by then
+fn bind_failed(addr: SocketAddr, proto: &str, e: &io::Error, bound: &[SocketAddr]) {
+    let dual_stack = SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), addr.port());
+    if e.kind() == ErrorKind::AddrInUse
+        && addr.ip() == Ipv4Addr::UNSPECIFIED
+        && bound.contains(&dual_stack)
+    {
+        log_debug!("{}/{} is covered by {}/{}", addr, proto, dual_stack, proto);
+    } else {
+        log_warn!("Not listening on {}/{}: {}", addr, proto, e);
+    }
+}
+
+fn run(config: Config) -> Result<()> {
+    set_level(config.log_level);
+
+    // Bind an UDP socket on every address, and a TCP listener on the same
+    // address for clients whose answers don't fit in a datagram
+    let mut sockets = Vec::new();
+    let mut listeners = Vec::new();
+    let (mut udp_bound, mut tcp_bound) = (Vec::new(), Vec::new());
+    for addr in config.listen_addrs() {
+        match UdpSocket::bind(addr) {
+            Ok(socket) => {
+                sockets.push(Arc::new(socket));
+                udp_bound.push(addr);
             }
-            for rec in result.resources {
-                println!("Resource: {:?}", rec);
-                packet.resources.push(rec);
+            Err(e) => bind_failed(addr, "udp", &e, &udp_bound),
+        }
+        match TcpListener::bind(addr) {
+            Ok(listener) => {
+                listeners.push(listener);
+                tcp_bound.push(addr);
             }
-        } else {
-            packet.header.rescode = ResultCode::SERVFAIL;
+            Err(e) => bind_failed(addr, "tcp", &e, &tcp_bound),
         }
     }
-    // Being mindful of how unreliable input data from arbitrary senders can be, we
-    // need make sure that a question is actually present. If not, we return `FORMERR`
-    // to indicate that the sender made something wrong.
-    else {
-        packet.header.rescode = ResultCode::FORMERR;
+    if sockets.is_empty() {
+        return Err("Failed to bind any UDP socket".into());
     }
 
-    // encode our response and send it back
-    let mut res_buffer = BytePacketBuffer::new();
-    packet.write(&mut res_buffer)?;
+    let mut resolver = config.resolver;
+    resolver.root_hints = match config.root_hints {
+        Some(ref path) => RootHints::from_file(path)
+            .map_err(|e| format!("resolver.root_hints: {}: {}", path.display(), e))?,
+        None if Path::new(ROOT_HINTS_FILE).exists() => RootHints::from_file(ROOT_HINTS_FILE)?,
+        None => RootHints::builtin(),
+    };
 
-    let len = res_buffer.pos();
-    let data = res_buffer.get_range(0, len)?;
+    let mut authority = Authority::new();
+    for (origin, path) in &config.zones {
+        let zone = Zone::load(origin, path).map_err(|e| format!("zones.{}: {}", origin, e))?;
+        log_info!("loaded zone {} from {}", origin, path.display());
+        authority.add_zone(zone);
+    }
+
+    let forwarder = match config.mode {
+        Mode::Forwarding => {
+            let mut forwarder = Forwarder::new(config.upstreams, config.selection);
+            forwarder.fallback_to_recursion = config.fallback_to_recursion;
+            Some(forwarder)
+        }
+        _ => None,
+    };
+
+    // The cache and configuration are shared by all queries
+    let context = Arc::new(ServerContext {
+        mode: config.mode,
+        cache: DnsCache::with_capacity(config.cache_size),
+        resolver,
+        forwarder,
+        authority,
+        acl: config.acl,
+    });
+
+    // Only a server that may resolve from the root needs to know the root
+    // servers
+    let recurses = match config.mode {
+        Mode::Recursive => true,
+        Mode::Forwarding => config.fallback_to_recursion,
+        Mode::Authoritative => false,
+    };
+    if recurses {
+        let context = context.clone();
+        thread::spawn(move || keep_root_servers_primed(context));
+    }
+
+    // Queries from both transports are resolved concurrently by a shared pool
+    // of workers, which bounds the number of lookups in flight.
+    let pool = Arc::new(ThreadPool::new(config.workers, config.queue_size));
 
-    socket.send_to(data, src)?;
+    // Every socket and listener is served on its own thread
+    let mut handles = Vec::new();
+    for listener in listeners {
+        let context = context.clone();
+        let pool = pool.clone();
+        handles.push(thread::spawn(move || serve_tcp(listener, context, pool)));
+    }
+    for socket in sockets {
+        let context = context.clone();
+        let pool = pool.clone();
+        handles.push(thread::spawn(move || serve_udp(socket, context, pool)));
+    }
+
+    for handle in handles {
+        let _ = handle.join();
+    }
 
     Ok(())
 }
 
-fn main() -> Result<()> {
-    // Bind an UDP socket on port 2053
-    let socket = UdpSocket::bind(("0.0.0.0", 2053))?;
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
 
-    // For now, queries are handled sequentially, so an infinite loop for servicing
-    // requests is initiated.
-    loop {
-        match handle_query(&socket) {
-            Ok(_) => {}
-            Err(e) => eprintln!("An error occurred: {}", e),
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
         }
+    };
+
+    if let Err(e) = run(config) {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
@@ -0,0 +1,268 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// The transport a query arrived over, which may have rules of its own
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// A block of addresses, such as 192.0.2.0/24 or 2001:db8::/32
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// A bare address is a block of just that address
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {:?}", s))?,
+            None => max,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+/// Acl is an ordered list of address blocks that are allowed, or denied
+/// when written with a leading `!`. The first block a client falls in
+/// decides, and clients that fall in none are denied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Acl {
+    rules: Vec<(bool, Cidr)>,
+}
+
+impl Acl {
+    /// Allow everyone
+    pub fn any() -> Acl {
+        Acl::parse(&["any"]).unwrap()
+    }
+
+    /// Allow only the host itself and the private networks, which is where
+    /// a resolver's clients normally are
+    pub fn private() -> Acl {
+        Acl::parse(&[
+            "127.0.0.0/8",
+            "::1",
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "fc00::/7",
+        ])
+        .unwrap()
+    }
+
+    /// Build a list from blocks such as "10.0.0.0/8" or "!10.0.0.1", where
+    /// "any" stands for every address and "none" for no address at all
+    pub fn parse(rules: &[&str]) -> Result<Acl, String> {
+        let mut acl = Acl { rules: Vec::new() };
+
+        for rule in rules {
+            let (allow, block) = match rule.trim().strip_prefix('!') {
+                Some(block) => (false, block),
+                None => (true, rule.trim()),
+            };
+            match block {
+                "any" => {
+                    acl.rules.push((allow, "0.0.0.0/0".parse()?));
+                    acl.rules.push((allow, "::/0".parse()?));
+                }
+                "none" => {}
+                _ => acl.rules.push((allow, block.parse()?)),
+            }
+        }
+
+        Ok(acl)
+    }
+
+    pub fn allows(&self, client: &IpAddr) -> bool {
+        // IPv4 clients of a dual-stack socket show up as IPv4-mapped IPv6
+        // addresses, which the IPv4 rules should apply to
+        let client = client.to_canonical();
+
+        self.rules
+            .iter()
+            .find(|(_, block)| block.contains(&client))
+            .is_some_and(|(allow, _)| *allow)
+    }
+}
+
+/// Rules that replace the general ones for a single transport
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransportRules {
+    pub query: Option<Acl>,
+    pub recursion: Option<Acl>,
+}
+
+/// AccessControl decides who may ask us questions at all, and who may have
+/// us resolve names we aren't authoritative for. Without it, anyone who can
+/// reach the server could use it as an open resolver, and with spoofed
+/// source addresses, to amplify attacks on others.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessControl {
+    /// Who may query the server
+    pub query: Acl,
+    /// Who may have names resolved for them, by recursion or forwarding
+    pub recursion: Acl,
+    pub udp: TransportRules,
+    pub tcp: TransportRules,
+    /// Who may query our zones, by origin, which replaces `query` for the
+    /// names in them so that public zones can be served to everyone
+    pub zones: Vec<(String, Acl)>,
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        AccessControl {
+            query: Acl::any(),
+            recursion: Acl::private(),
+            udp: TransportRules::default(),
+            tcp: TransportRules::default(),
+            zones: Vec::new(),
+        }
+    }
+}
+
+impl AccessControl {
+    fn transport(&self, transport: Transport) -> &TransportRules {
+        match transport {
+            Transport::Udp => &self.udp,
+            Transport::Tcp => &self.tcp,
+        }
+    }
+
+    /// Whether `client` may query for names in the zone at `origin`, or for
+    /// names outside of our zones when there is none
+    pub fn allows_query(
+        &self,
+        client: &IpAddr,
+        transport: Transport,
+        origin: Option<&str>,
+    ) -> bool {
+        let zone = origin.and_then(|origin| {
+            self.zones
+                .iter()
+                .find(|(zone, _)| zone == origin)
+                .map(|(_, acl)| acl)
+        });
+
+        zone.or(self.transport(transport).query.as_ref())
+            .unwrap_or(&self.query)
+            .allows(client)
+    }
+
+    /// Whether `client` may have names resolved for them
+    pub fn allows_recursion(&self, client: &IpAddr, transport: Transport) -> bool {
+        self.transport(transport)
+            .recursion
+            .as_ref()
+            .unwrap_or(&self.recursion)
+            .allows(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr() {
+        let block: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(block.contains(&ip("192.168.10.1")));
+        assert!(!block.contains(&ip("192.169.0.1")));
+        assert!(!block.contains(&ip("::1")));
+
+        let block: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(block.contains(&ip("2001:db8:1::1")));
+        assert!(!block.contains(&ip("2001:db9::1")));
+
+        assert!("0.0.0.0/0"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("8.8.8.8")));
+        assert!("10.0.0.1"
+            .parse::<Cidr>()
+            .unwrap()
+            .contains(&ip("10.0.0.1")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_acl() {
+        let acl = Acl::parse(&["!10.0.0.1", "10.0.0.0/8"]).unwrap();
+        assert!(acl.allows(&ip("10.1.2.3")));
+        assert!(!acl.allows(&ip("10.0.0.1")));
+        assert!(!acl.allows(&ip("192.0.2.1")));
+
+        // IPv4 clients of an IPv6 socket
+        assert!(acl.allows(&ip("::ffff:10.1.2.3")));
+        assert!(Acl::private().allows(&ip("::ffff:127.0.0.1")));
+        assert!(!Acl::private().allows(&ip("203.0.113.1")));
+
+        assert!(!Acl::parse(&["none"]).unwrap().allows(&ip("::1")));
+        assert!(Acl::parse(&["any"]).unwrap().allows(&ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn test_access_control() {
+        let access = AccessControl {
+            query: Acl::parse(&["10.0.0.0/8"]).unwrap(),
+            tcp: TransportRules {
+                recursion: Some(Acl::any()),
+                ..TransportRules::default()
+            },
+            zones: vec![("example.com".to_string(), Acl::any())],
+            ..AccessControl::default()
+        };
+        let outsider = ip("203.0.113.1");
+
+        assert!(!access.allows_query(&outsider, Transport::Udp, None));
+        assert!(access.allows_query(&ip("10.0.0.1"), Transport::Udp, None));
+        // Public zones are served to everyone
+        assert!(access.allows_query(&outsider, Transport::Udp, Some("example.com")));
+        assert!(!access.allows_query(&outsider, Transport::Udp, Some("example.org")));
+
+        // Recursion over TCP can't be used for amplification
+        assert!(!access.allows_recursion(&outsider, Transport::Udp));
+        assert!(access.allows_recursion(&outsider, Transport::Tcp));
+    }
+}
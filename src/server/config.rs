@@ -0,0 +1,844 @@
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::dns::dns_cache::DEFAULT_MAX_ENTRIES;
+use crate::dns::dns_lookup::{IpPolicy, ResolverConfig};
+use crate::dns::forwarder::Selection;
+use crate::utils::log::LogLevel;
+use crate::utils::types::Result;
+
+use super::acl::{AccessControl, Acl};
+
+pub const USAGE: &str = "usage: dns [--config FILE] [--listen ADDR]... [--port PORT] [--mode MODE]
+           [--upstream ADDR]... [--zone ORIGIN=FILE]... [--root-hints FILE]
+           [--cache-size ENTRIES] [--query-timeout DURATION] [--log-level LEVEL]
+           [--allow-query CIDR]... [--allow-recursion CIDR]... [--set KEY=VALUE]...";
+
+/// What the server does with questions for names outside of its own zones
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Resolve them from the root
+    #[default]
+    Recursive,
+    /// Send them on to upstream resolvers
+    Forwarding,
+    /// Refuse them, and only answer for our own zones
+    Authoritative,
+}
+
+/// Config holds everything about how the server runs, as read from the
+/// configuration file and the command line
+#[derive(Debug)]
+pub struct Config {
+    /// The addresses to listen on, with the port to use when none is given
+    pub listen: Vec<(IpAddr, Option<u16>)>,
+    pub port: u16,
+    pub mode: Mode,
+    /// How many queries are resolved at the same time
+    pub workers: usize,
+    /// How many received queries may wait for a free worker
+    pub queue_size: usize,
+    pub log_level: LogLevel,
+    pub resolver: ResolverConfig,
+    /// A root hints file to use instead of the built-in hints
+    pub root_hints: Option<PathBuf>,
+    pub cache_size: usize,
+    pub upstreams: Vec<SocketAddr>,
+    pub selection: Selection,
+    pub fallback_to_recursion: bool,
+    /// The zones we are authoritative for, by origin, and their zone files
+    pub zones: Vec<(String, PathBuf)>,
+    pub acl: AccessControl,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            // On systems where IPv6 sockets are dual-stack by default, such
+            // as Linux, `::` accepts IPv4 clients as well and the IPv4
+            // wildcard then fails to bind, which is fine. Elsewhere, or on
+            // hosts without IPv6, the IPv4 wildcard makes sure IPv4 clients
+            // are served.
+            listen: vec![
+                ("::".parse().unwrap(), None),
+                ("0.0.0.0".parse().unwrap(), None),
+            ],
+            port: 2053,
+            mode: Mode::Recursive,
+            // Since a recursive lookup spends most of its time waiting on
+            // the network, this can comfortably be larger than the number
+            // of cores
+            workers: 32,
+            queue_size: 256,
+            log_level: LogLevel::Info,
+            resolver: ResolverConfig::default(),
+            root_hints: None,
+            cache_size: DEFAULT_MAX_ENTRIES,
+            upstreams: Vec::new(),
+            selection: Selection::RoundRobin,
+            fallback_to_recursion: true,
+            zones: Vec::new(),
+            acl: AccessControl::default(),
+        }
+    }
+}
+
+/// A value in the configuration file, which is a small subset of TOML
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    fn as_str(&self) -> std::result::Result<&str, String> {
+        match self {
+            Value::String(s) => Ok(s),
+            _ => Err("expected a string".to_string()),
+        }
+    }
+
+    fn as_bool(&self) -> std::result::Result<bool, String> {
+        match self {
+            Value::Boolean(b) => Ok(*b),
+            _ => Err("expected true or false".to_string()),
+        }
+    }
+
+    fn as_usize(&self) -> std::result::Result<usize, String> {
+        match self {
+            Value::Integer(n) => usize::try_from(*n).map_err(|_| format!("{} is out of range", n)),
+            _ => Err("expected an integer".to_string()),
+        }
+    }
+
+    fn as_positive(&self) -> std::result::Result<usize, String> {
+        match self.as_usize()? {
+            0 => Err("has to be at least 1".to_string()),
+            n => Ok(n),
+        }
+    }
+
+    fn as_acl(&self) -> std::result::Result<Acl, String> {
+        Acl::parse(&self.as_str_list()?)
+    }
+
+    // A single string is accepted where a list of them is expected
+    fn as_str_list(&self) -> std::result::Result<Vec<&str>, String> {
+        match self {
+            Value::String(s) => Ok(vec![s]),
+            Value::Array(values) => values.iter().map(Value::as_str).collect(),
+            _ => Err("expected a list of strings".to_string()),
+        }
+    }
+
+    /// Durations are either a number of seconds, or a string with a unit:
+    /// "500ms", "2s" or "1m"
+    fn as_duration(&self) -> std::result::Result<Duration, String> {
+        let duration = match self {
+            Value::Integer(n) => u64::try_from(*n).ok().map(Duration::from_secs),
+            Value::String(s) => parse_duration(s),
+            _ => None,
+        };
+
+        match duration {
+            Some(duration) if !duration.is_zero() => Ok(duration),
+            Some(_) => Err("has to be longer than zero".to_string()),
+            None => Err("expected a duration such as 500ms, 2s or 1m".to_string()),
+        }
+    }
+}
+
+fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    match unit.trim() {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "m" => Some(Duration::from_secs(number * 60)),
+        _ => None,
+    }
+}
+
+// Parse an address with an optional port, where IPv6 addresses with a port
+// are written in brackets
+fn parse_addr(s: &str) -> std::result::Result<(IpAddr, Option<u16>), String> {
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Ok((ip, None));
+    }
+    match s.parse::<SocketAddr>() {
+        Ok(addr) => Ok((addr.ip(), Some(addr.port()))),
+        Err(_) => Err(format!("invalid address {:?}", s)),
+    }
+}
+
+/// Reads values out of a line of the configuration file
+struct ValueParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl ValueParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn parse(&mut self) -> std::result::Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('"') => {
+                self.chars.next();
+                self.parse_string()
+            }
+            Some('[') => {
+                self.chars.next();
+                self.parse_array()
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = self
+                    .chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .map(Value::Integer)
+                        .map_err(|_| format!("invalid value {:?}", word)),
+                }
+            }
+            None => Err("missing value".to_string()),
+        }
+    }
+
+    fn parse_string(&mut self) -> std::result::Result<Value, String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(Value::String(s)),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some(c @ ('"' | '\\')) => s.push(c),
+                    _ => return Err("invalid escape in string".to_string()),
+                },
+                Some(c) => s.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> std::result::Result<Value, String> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_whitespace();
+            if self.chars.next_if_eq(&']').is_some() {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.parse()?);
+
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                _ => return Err("expected , or ] in list".to_string()),
+            }
+        }
+    }
+}
+
+fn parse_value(text: &str) -> std::result::Result<Value, String> {
+    let mut parser = ValueParser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.parse()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Ok(value),
+        Some(_) => Err(format!("unexpected text after value in {:?}", text.trim())),
+    }
+}
+
+// Strip a comment from a line, minding the # signs in strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+// Whether a list continues on the next line
+fn is_unbalanced(text: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '[' if !in_string => depth += 1,
+            ']' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+impl Config {
+    /// Read the configuration file at `path`. Settings that only make sense
+    /// together aren't checked yet, since flags may still complete them.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        let mut config = Config::default();
+        config
+            .read(&text)
+            .map_err(|e| format!("{}:{}", path.display(), e))?;
+
+        Ok(config)
+    }
+
+    /// Apply the settings in the text of a configuration file. Errors start
+    /// with the number of the offending line.
+    fn read(&mut self, text: &str) -> std::result::Result<(), String> {
+        let mut section = String::new();
+        let mut seen = HashSet::new();
+        let mut lines = text.lines().enumerate();
+
+        while let Some((idx, line)) = lines.next() {
+            let line_number = idx + 1;
+            let mut line = strip_comment(line).trim().to_string();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                section = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| format!("{}: expected ] after section name", line_number))?
+                    .trim()
+                    .to_string();
+                continue;
+            }
+
+            // Lists may span several lines
+            while is_unbalanced(&line) {
+                match lines.next() {
+                    Some((_, next)) => {
+                        line.push(' ');
+                        line.push_str(strip_comment(next).trim());
+                    }
+                    None => return Err(format!("{}: unterminated list", line_number)),
+                }
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}: expected key = value", line_number))?;
+            let key = key.trim();
+            let key = key
+                .strip_prefix('"')
+                .and_then(|key| key.strip_suffix('"'))
+                .unwrap_or(key);
+            let key = if section.is_empty() {
+                key.to_string()
+            } else {
+                format!("{}.{}", section, key)
+            };
+
+            if !seen.insert(key.clone()) {
+                return Err(format!("{}: {}: set more than once", line_number, key));
+            }
+            let value =
+                parse_value(value).map_err(|e| format!("{}: {}: {}", line_number, key, e))?;
+            self.set(&key, &value)
+                .map_err(|e| format!("{}: {}", line_number, e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Set a single key, such as `resolver.query_timeout`. Errors start
+    /// with the name of the key.
+    fn set(&mut self, key: &str, value: &Value) -> std::result::Result<(), String> {
+        self.set_value(key, value)
+            .map_err(|e| format!("{}: {}", key, e))
+    }
+
+    fn set_value(&mut self, key: &str, value: &Value) -> std::result::Result<(), String> {
+        if let Some(origin) = key.strip_prefix("zones.") {
+            let origin = origin.trim_end_matches('.').to_lowercase();
+            self.zones.retain(|(existing, _)| *existing != origin);
+            self.zones.push((origin, PathBuf::from(value.as_str()?)));
+            return Ok(());
+        }
+        if let Some(origin) = key.strip_prefix("acl.zones.") {
+            let origin = origin.trim_end_matches('.').to_lowercase();
+            self.acl.zones.retain(|(existing, _)| *existing != origin);
+            self.acl.zones.push((origin, value.as_acl()?));
+            return Ok(());
+        }
+
+        match key {
+            "server.listen" => {
+                self.listen = value
+                    .as_str_list()?
+                    .into_iter()
+                    .map(parse_addr)
+                    .collect::<std::result::Result<_, _>>()?;
+            }
+            "server.port" => {
+                self.port = match value.as_usize()? {
+                    port @ 1..=65535 => port as u16,
+                    port => return Err(format!("invalid port {}", port)),
+                };
+            }
+            "server.mode" => {
+                self.mode = match value.as_str()? {
+                    "recursive" => Mode::Recursive,
+                    "forwarding" => Mode::Forwarding,
+                    "authoritative" => Mode::Authoritative,
+                    mode => {
+                        return Err(format!(
+                            "unknown mode {:?}, expected recursive, forwarding or authoritative",
+                            mode
+                        ))
+                    }
+                };
+            }
+            "server.workers" => self.workers = value.as_positive()?,
+            "server.queue_size" => self.queue_size = value.as_positive()?,
+            "server.log_level" => self.log_level = value.as_str()?.parse()?,
+            "resolver.query_timeout" => self.resolver.query_timeout = value.as_duration()?,
+            "resolver.resolution_timeout" => {
+                self.resolver.resolution_timeout = value.as_duration()?
+            }
+            "resolver.retries" => self.resolver.retries = value.as_usize()?,
+            "resolver.ip_policy" => {
+                self.resolver.ip_policy = match value.as_str()? {
+                    "ipv4-only" => IpPolicy::Ipv4Only,
+                    "ipv6-only" => IpPolicy::Ipv6Only,
+                    "prefer-ipv4" => IpPolicy::PreferIpv4,
+                    "prefer-ipv6" => IpPolicy::PreferIpv6,
+                    policy => {
+                        return Err(format!(
+                            "unknown policy {:?}, expected ipv4-only, ipv6-only, prefer-ipv4 or prefer-ipv6",
+                            policy
+                        ))
+                    }
+                };
+            }
+            "resolver.qname_minimisation" => self.resolver.qname_minimisation = value.as_bool()?,
+            "resolver.max_queries" => self.resolver.max_queries = value.as_positive()?,
+            "resolver.max_ns_depth" => self.resolver.max_ns_depth = value.as_positive()?,
+            "resolver.max_referrals" => self.resolver.max_referrals = value.as_positive()?,
+            "resolver.root_hints" => self.root_hints = Some(PathBuf::from(value.as_str()?)),
+            "cache.max_entries" => self.cache_size = value.as_usize()?,
+            "forwarder.upstreams" => {
+                self.upstreams = value
+                    .as_str_list()?
+                    .into_iter()
+                    .map(|s| {
+                        parse_addr(s).map(|(ip, port)| SocketAddr::new(ip, port.unwrap_or(53)))
+                    })
+                    .collect::<std::result::Result<_, _>>()?;
+            }
+            "forwarder.selection" => {
+                self.selection = match value.as_str()? {
+                    "round-robin" => Selection::RoundRobin,
+                    "lowest-latency" => Selection::LowestLatency,
+                    selection => {
+                        return Err(format!(
+                            "unknown selection {:?}, expected round-robin or lowest-latency",
+                            selection
+                        ))
+                    }
+                };
+            }
+            "forwarder.fallback_to_recursion" => self.fallback_to_recursion = value.as_bool()?,
+            "acl.query" => self.acl.query = value.as_acl()?,
+            "acl.recursion" => self.acl.recursion = value.as_acl()?,
+            "acl.udp.query" => self.acl.udp.query = Some(value.as_acl()?),
+            "acl.udp.recursion" => self.acl.udp.recursion = Some(value.as_acl()?),
+            "acl.tcp.query" => self.acl.tcp.query = Some(value.as_acl()?),
+            "acl.tcp.recursion" => self.acl.tcp.recursion = Some(value.as_acl()?),
+            _ => return Err("unknown key".to_string()),
+        }
+
+        Ok(())
+    }
+
+    /// Check the settings that only make sense together
+    pub fn validate(&self) -> Result<()> {
+        if self.listen.is_empty() {
+            return Err("server.listen: at least one address is needed".into());
+        }
+        if self.mode == Mode::Forwarding && self.upstreams.is_empty() {
+            return Err("forwarder.upstreams: forwarding mode needs at least one upstream".into());
+        }
+        if self.mode == Mode::Authoritative && self.zones.is_empty() {
+            return Err("zones: authoritative mode needs at least one zone".into());
+        }
+        for (origin, _) in &self.acl.zones {
+            if !self.zones.iter().any(|(zone, _)| zone == origin) {
+                return Err(format!("acl.zones.{}: not one of our zones", origin).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the configuration from the command line, which may point at a
+    /// configuration file and override any of its settings
+    pub fn from_args(args: &[String]) -> Result<Config> {
+        let mut config = Config::default();
+        let mut overrides = Vec::new();
+        let mut listen = Vec::new();
+        let mut upstreams = Vec::new();
+        let mut allow_query = Vec::new();
+        let mut allow_recursion = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let arg = args
+                .next()
+                .ok_or_else(|| format!("{} needs a value\n{}", flag, USAGE))?;
+            // Bare words are taken as strings, which saves quoting them
+            // twice on the command line
+            let value = parse_value(arg).unwrap_or(Value::String(arg.clone()));
+
+            // Flags are shorthands for keys of the configuration file
+            let key = match flag.as_str() {
+                "--config" => {
+                    config = Config::load(arg)?;
+                    continue;
+                }
+                "--listen" => {
+                    listen.push(Value::String(arg.clone()));
+                    continue;
+                }
+                "--upstream" => {
+                    upstreams.push(Value::String(arg.clone()));
+                    continue;
+                }
+                "--allow-query" => {
+                    allow_query.push(Value::String(arg.clone()));
+                    continue;
+                }
+                "--allow-recursion" => {
+                    allow_recursion.push(Value::String(arg.clone()));
+                    continue;
+                }
+                "--zone" => {
+                    let (origin, path) = arg
+                        .split_once('=')
+                        .ok_or_else(|| format!("{}: expected ORIGIN=FILE", flag))?;
+                    overrides.push((
+                        flag.clone(),
+                        format!("zones.{}", origin),
+                        Value::String(path.to_string()),
+                    ));
+                    continue;
+                }
+                "--set" => {
+                    let (key, value) = arg
+                        .split_once('=')
+                        .ok_or_else(|| format!("{}: expected KEY=VALUE", flag))?;
+                    let value = parse_value(value).unwrap_or(Value::String(value.to_string()));
+                    overrides.push((flag.clone(), key.to_string(), value));
+                    continue;
+                }
+                "--root-hints" => {
+                    overrides.push((
+                        flag.clone(),
+                        "resolver.root_hints".to_string(),
+                        Value::String(arg.clone()),
+                    ));
+                    continue;
+                }
+                "--port" => "server.port",
+                "--mode" => "server.mode",
+                "--log-level" => "server.log_level",
+                "--cache-size" => "cache.max_entries",
+                "--query-timeout" => "resolver.query_timeout",
+                _ => return Err(format!("unknown option {}\n{}", flag, USAGE).into()),
+            };
+            overrides.push((flag.clone(), key.to_string(), value));
+        }
+
+        // Repeated flags make up a single list, which replaces the one from
+        // the file
+        if !listen.is_empty() {
+            overrides.push((
+                "--listen".to_string(),
+                "server.listen".to_string(),
+                Value::Array(listen),
+            ));
+        }
+        if !upstreams.is_empty() {
+            overrides.push((
+                "--upstream".to_string(),
+                "forwarder.upstreams".to_string(),
+                Value::Array(upstreams),
+            ));
+        }
+        if !allow_query.is_empty() {
+            overrides.push((
+                "--allow-query".to_string(),
+                "acl.query".to_string(),
+                Value::Array(allow_query),
+            ));
+        }
+        if !allow_recursion.is_empty() {
+            overrides.push((
+                "--allow-recursion".to_string(),
+                "acl.recursion".to_string(),
+                Value::Array(allow_recursion),
+            ));
+        }
+
+        // The command line wins over the file, whatever the order of the
+        // flags
+        for (flag, key, value) in overrides {
+            config
+                .set(&key, &value)
+                .map_err(|e| format!("{}: {}", flag, e))?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The addresses to listen on, with the default port filled in
+    pub fn listen_addrs(&self) -> Vec<SocketAddr> {
+        self.listen
+            .iter()
+            .map(|(ip, port)| SocketAddr::new(*ip, port.unwrap_or(self.port)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_read() {
+        let text = r#"
+# Forward everything except our own zone
+[server]
+listen = [
+    "127.0.0.1",        # on the default port
+    "[::1]:5353",
+]
+mode = "forwarding"
+log_level = "debug"
+
+[resolver]
+query_timeout = "500ms"
+resolution_timeout = 5
+qname_minimisation = false
+
+[cache]
+max_entries = 10_000
+
+[forwarder]
+upstreams = ["9.9.9.9", "[2620:fe::fe]:53"]
+selection = "lowest-latency"
+
+[zones]
+"example.com." = "zones/example.com.zone"
+
+[acl]
+recursion = ["!10.0.0.1", "10.0.0.0/8"]
+
+[acl.tcp]
+recursion = "any"
+
+[acl.zones]
+"example.com" = ["any"]
+"#;
+        let mut config = Config::default();
+        config.read(text).unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(
+            config.listen_addrs(),
+            vec![
+                "127.0.0.1:2053".parse().unwrap(),
+                "[::1]:5353".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.mode, Mode::Forwarding);
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.resolver.query_timeout, Duration::from_millis(500));
+        assert_eq!(config.resolver.resolution_timeout, Duration::from_secs(5));
+        assert!(!config.resolver.qname_minimisation);
+        assert_eq!(config.cache_size, 10_000);
+        assert_eq!(config.upstreams[0], "9.9.9.9:53".parse().unwrap());
+        assert_eq!(config.selection, Selection::LowestLatency);
+        assert_eq!(
+            config.zones,
+            vec![(
+                "example.com".to_string(),
+                PathBuf::from("zones/example.com.zone")
+            )]
+        );
+        assert_eq!(
+            config.acl.recursion,
+            Acl::parse(&["!10.0.0.1", "10.0.0.0/8"]).unwrap()
+        );
+        assert_eq!(config.acl.tcp.recursion, Some(Acl::any()));
+        assert_eq!(config.acl.udp.recursion, None);
+        assert_eq!(config.acl.zones[0].0, "example.com");
+    }
+
+    #[test]
+    fn test_errors_name_the_key() {
+        let read = |text: &str| Config::default().read(text).unwrap_err();
+
+        assert_eq!(
+            read("[server]\nworkers = \"many\""),
+            "2: server.workers: expected an integer"
+        );
+        assert_eq!(
+            read("\n[resolver]\nquery_timeout = \"soon\""),
+            "3: resolver.query_timeout: expected a duration such as 500ms, 2s or 1m"
+        );
+        assert_eq!(read("[server]\nlisten = [\"::\""), "2: unterminated list");
+        assert_eq!(read("[cache]\nsize = 1"), "2: cache.size: unknown key");
+        assert_eq!(
+            read("[server]\nport = 1\nport = 2"),
+            "3: server.port: set more than once"
+        );
+        assert!(read("[server]\nmode = \"caching\"").starts_with("2: server.mode: "));
+        assert_eq!(
+            read("[acl]\nquery = [\"10.0.0.0/40\"]"),
+            "2: acl.query: invalid prefix length in \"10.0.0.0/40\""
+        );
+
+        let config = Config {
+            mode: Mode::Forwarding,
+            ..Config::default()
+        };
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .starts_with("forwarder.upstreams: "));
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args(&args(&[
+            "--port",
+            "53",
+            "--listen",
+            "127.0.0.1",
+            "--listen",
+            "::1",
+            "--mode",
+            "forwarding",
+            "--upstream",
+            "1.1.1.1",
+            "--set",
+            "resolver.retries=4",
+            "--query-timeout",
+            "3s",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.listen_addrs()[1], "[::1]:53".parse().unwrap());
+        assert_eq!(config.mode, Mode::Forwarding);
+        assert_eq!(config.upstreams, vec!["1.1.1.1:53".parse().unwrap()]);
+        assert_eq!(config.resolver.retries, 4);
+        assert_eq!(config.resolver.query_timeout, Duration::from_secs(3));
+
+        let err = Config::from_args(&args(&["--cache-size", "lots"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--cache-size: cache.max_entries: expected an integer"
+        );
+        assert!(Config::from_args(&args(&["--mode", "authoritative"])).is_err());
+        assert!(Config::from_args(&args(&["--verbose"])).is_err());
+
+        let err = Config::from_args(&args(&["--set", "acl.zones.example.com=any"])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "acl.zones.example.com: not one of our zones"
+        );
+    }
+
+    #[test]
+    fn test_from_args_completes_file() {
+        let dir = env::temp_dir().join(format!("dns-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let forwarding = dir.join("forwarding.toml");
+        fs::write(&forwarding, "[server]\nmode = \"forwarding\"\n").unwrap();
+        let authoritative = dir.join("authoritative.toml");
+        fs::write(&authoritative, "[server]\nmode = \"authoritative\"\n").unwrap();
+        let path = |path: &PathBuf| path.display().to_string();
+
+        // The file is only checked once the flags have been applied
+        let config = Config::from_args(&args(&[
+            "--config",
+            &path(&forwarding),
+            "--upstream",
+            "9.9.9.9",
+        ]));
+        assert_eq!(
+            config.unwrap().upstreams,
+            vec!["9.9.9.9:53".parse().unwrap()]
+        );
+        let config = Config::from_args(&args(&[
+            "--zone",
+            "example.com=example.com.zone",
+            "--config",
+            &path(&authoritative),
+        ]));
+        assert_eq!(config.unwrap().mode, Mode::Authoritative);
+
+        assert!(Config::from_args(&args(&["--config", &path(&forwarding)])).is_err());
+        assert!(Config::load(&forwarding).is_ok());
+
+        // The list for a zone may come before the zone itself
+        let public = dir.join("public.toml");
+        fs::write(&public, "[acl.zones]\n\"example.com\" = [\"any\"]\n").unwrap();
+        let config = Config::from_args(&args(&[
+            "--config",
+            &path(&public),
+            "--zone",
+            "example.com=example.com.zone",
+        ]));
+        assert_eq!(config.unwrap().acl.zones[0].0, "example.com");
+        assert!(Config::from_args(&args(&["--config", &path(&public)])).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+use crate::dns::dns_cache::DnsCache;
+use crate::dns::dns_lookup::ResolverConfig;
+use crate::dns::forwarder::Forwarder;
+use crate::zone::authority::Authority;
+
+use super::acl::AccessControl;
+use super::config::Mode;
+
+/// The state shared by every query the server handles
+#[derive(Default)]
+pub struct ServerContext {
+    pub mode: Mode,
+    // Records learned while resolving are kept around for as long as their
+    // TTL allows
+    pub cache: DnsCache,
+    pub resolver: ResolverConfig,
+    // When set, questions are forwarded to other resolvers rather than
+    // resolved from the root
+    pub forwarder: Option<Forwarder>,
+    // The zones we answer for ourselves, without recursing or forwarding
+    pub authority: Authority,
+    // Who may query us, and who may have us recurse
+    pub acl: AccessControl,
+}
@@ -0,0 +1,412 @@
+use std::net::IpAddr;
+
+use crate::dns::dns_cache::DnsCache;
+use crate::dns::dns_header::ResultCode;
+use crate::dns::dns_lookup::{recursive_lookup, LimitExceeded};
+use crate::dns::dns_packet::{DnsPacket, MAX_UDP_PAYLOAD_SIZE};
+use crate::dns::dns_question::DnsQuestion;
+use crate::dns::dns_record::DnsRecord;
+use crate::dns::edns_option::EdnsOption;
+use crate::dns::query_type::QueryType;
+use crate::utils::types::Result;
+use crate::{log_info, log_warn};
+
+use super::acl::Transport;
+use super::config::Mode;
+use super::context::ServerContext;
+
+/// The extended DNS error (RFC 8914) sent along with a SERVFAIL when a
+/// resolution ran into a limit. None of the registered codes describe this,
+/// so it's "Other", with the reason in the extra text.
+const EDE_OTHER: u16 = 0;
+
+fn refused() -> DnsPacket {
+    let mut packet = DnsPacket::new();
+    packet.header.rescode = ResultCode::REFUSED;
+    packet
+}
+
+// Whether we'll resolve names outside of our zones for this client
+fn recursion_allowed(context: &ServerContext, client: &IpAddr, transport: Transport) -> bool {
+    context.mode != Mode::Authoritative
+        && context.acl.allows_query(client, transport, None)
+        && context.acl.allows_recursion(client, transport)
+}
+
+// Answer a question from what we already know, for clients that don't want
+// us to recurse: a cached answer, or else a referral to the closest name
+// servers we know of
+fn cached_answer(qname: &str, qtype: QueryType, cache: &DnsCache) -> DnsPacket {
+    if let Some(packet) = cache.lookup(qname, qtype) {
+        return packet;
+    }
+
+    let mut packet = DnsPacket::new();
+    let zone = match cache.lookup_closest_ns(qname) {
+        Some((zone, _)) => zone,
+        None => {
+            packet.header.rescode = ResultCode::SERVFAIL;
+            return packet;
+        }
+    };
+    for ns in cache.get(&zone, QueryType::NS).unwrap_or_default() {
+        if let DnsRecord::NS { ref host, .. } = ns {
+            for qtype in [QueryType::A, QueryType::AAAA] {
+                packet
+                    .resources
+                    .extend(cache.get(host, qtype).unwrap_or_default());
+            }
+        }
+        packet.authorities.push(ns);
+    }
+    packet
+}
+
+// Answer a question from our own zones if it falls in one of them, and
+// otherwise by forwarding it when upstreams are configured, or by recursing
+// from the root, unless the client asked us not to. Clients the access
+// control lists don't allow, and every client of a server that is only
+// authoritative, are refused. Along with the answer comes whether it is our
+// own, which is the only kind we may mark as authoritative.
+fn lookup(
+    question: &DnsQuestion,
+    context: &ServerContext,
+    client: &IpAddr,
+    transport: Transport,
+    recursion_desired: bool,
+) -> Result<(DnsPacket, bool)> {
+    let (qname, qtype) = (&question.name, question.question_type);
+
+    if let Some(zone) = context.authority.find_zone(qname) {
+        if !context
+            .acl
+            .allows_query(client, transport, Some(&zone.origin))
+        {
+            log_info!("Refused query for zone {} from {}", zone.origin, client);
+            return Ok((refused(), false));
+        }
+        return Ok((zone.answer(qname, qtype), true));
+    }
+
+    if !recursion_allowed(context, client, transport) {
+        log_info!("Refused recursive query from {}", client);
+        return Ok((refused(), false));
+    }
+
+    if !recursion_desired {
+        return Ok((cached_answer(qname, qtype, &context.cache), false));
+    }
+
+    if let Some(ref forwarder) = context.forwarder {
+        match forwarder.lookup(qname, qtype, &context.cache, &context.resolver) {
+            Ok(result) => return Ok((result, false)),
+            Err(e) if forwarder.fallback_to_recursion => {
+                log_warn!("Forwarding failed, falling back to recursion: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let result = recursive_lookup(qname, qtype, &context.cache, &context.resolver)?;
+    Ok((result, false))
+}
+
+/// Build the response to a single request from `client`. This is shared by
+/// the UDP and TCP listeners, which only differ in how they receive and send
+/// packets.
+pub fn handle_query(
+    mut request: DnsPacket,
+    context: &ServerContext,
+    client: IpAddr,
+    transport: Transport,
+) -> DnsPacket {
+    // Create and initialize the response packet
+    let mut packet = DnsPacket::new();
+    packet.header.id = request.header.id;
+    packet.header.recursion_desired = request.header.recursion_desired;
+    packet.header.recursion_available = recursion_allowed(context, &client, transport);
+    packet.header.response = true;
+
+    // If the client speaks EDNS(0), so do we
+    let mut edns_version = 0;
+    if let Some(DnsRecord::OPT {
+        version, dnssec_ok, ..
+    }) = request.edns
+    {
+        packet.set_edns(MAX_UDP_PAYLOAD_SIZE, dnssec_ok);
+        edns_version = version;
+    }
+
+    // We only implement version 0 of EDNS. Anything newer gets BADVERS, which
+    // is extended RCODE 16: the upper bits go in the OPT record.
+    if edns_version > 0 {
+        if let Some(DnsRecord::OPT {
+            ref mut extended_rcode,
+            ..
+        }) = packet.edns
+        {
+            *extended_rcode = 1;
+        }
+    }
+    // In the normal case, exactly one question is present
+    else if let Some(question) = request.questions.pop() {
+        log_info!("Received query: {}", question);
+
+        // Since all is set up and as expected, the query can be forwarded to the
+        // target server. There's always the possibility that the query will
+        // fail, in which case the `SERVFAIL` response code is set to indicate
+        // as much to the client. If rather everything goes as planned, the
+        // question and response records as copied into our response packet.
+        let recursion_desired = request.header.recursion_desired;
+        match lookup(&question, context, &client, transport, recursion_desired) {
+            Ok((result, authoritative)) => {
+                packet.questions.push(question);
+                packet.header.rescode = result.header.rescode;
+                // Answers relayed from other servers keep the AA bit they
+                // came with, but aren't ours to vouch for (RFC 1035 4.1.1)
+                packet.header.authoritative_answer = authoritative;
+
+                for rec in result.answers {
+                    log_info!("Answer: {}", rec);
+                    packet.answers.push(rec);
+                }
+                for rec in result.authorities {
+                    log_info!("Authority: {}", rec);
+                    packet.authorities.push(rec);
+                }
+                for rec in result.resources {
+                    log_info!("Resource: {}", rec);
+                    packet.resources.push(rec);
+                }
+            }
+            Err(e) => {
+                log_info!("Failed to resolve {}: {}", question, e);
+                packet.header.rescode = ResultCode::SERVFAIL;
+                if let Some(limit) = e.downcast_ref::<LimitExceeded>() {
+                    packet.add_edns_option(EdnsOption::EDE {
+                        info_code: EDE_OTHER,
+                        extra_text: limit.to_string(),
+                    });
+                }
+            }
+        }
+    }
+    // Being mindful of how unreliable input data from arbitrary senders can be, we
+    // need make sure that a question is actually present. If not, we return `FORMERR`
+    // to indicate that the sender made something wrong.
+    else {
+        packet.header.rescode = ResultCode::FORMERR;
+    }
+
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{SocketAddr, UdpSocket};
+    use std::thread;
+
+    use crate::buffer::buffer::BytePacketBuffer;
+    use crate::dns::forwarder::{Forwarder, Selection};
+    use crate::server::acl::{AccessControl, Acl, TransportRules};
+    use crate::zone::authority::{Authority, Zone};
+    use crate::zone::zone_file::parse_zone;
+
+    // A server for example.com, with the given access control. Questions
+    // the tests ask outside of it are all refused, so nothing goes upstream.
+    fn server(acl: AccessControl) -> ServerContext {
+        let text = "
+$TTL 3600
+@           SOA ns1 hostmaster 1 7200 3600 1209600 300
+            NS  ns1
+ns1         A   10.0.0.1
+www         A   10.0.0.2
+";
+        let mut authority = Authority::new();
+        authority
+            .add_zone(Zone::new("example.com", parse_zone(text, "example.com").unwrap()).unwrap());
+
+        ServerContext {
+            authority,
+            acl,
+            ..ServerContext::default()
+        }
+    }
+
+    fn request(name: &str, recursion_desired: bool) -> DnsPacket {
+        let mut request = DnsPacket::new();
+        request.header.id = 1234;
+        request.header.recursion_desired = recursion_desired;
+        request
+            .questions
+            .push(DnsQuestion::new(name.to_string(), QueryType::A));
+        request
+    }
+
+    fn query(context: &ServerContext, name: &str, client: &str, transport: Transport) -> DnsPacket {
+        handle_query(
+            request(name, true),
+            context,
+            client.parse().unwrap(),
+            transport,
+        )
+    }
+
+    #[test]
+    fn test_refused_clients() {
+        let context = server(AccessControl {
+            query: Acl::parse(&["10.0.0.0/8"]).unwrap(),
+            ..AccessControl::default()
+        });
+
+        let response = query(&context, "www.example.com", "203.0.113.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+        assert!(!response.header.recursion_available);
+        assert!(response.answers.is_empty());
+
+        // Nor may they recurse, which their private address alone would allow
+        let response = query(&context, "www.example.org", "192.168.0.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+        assert!(!response.header.recursion_available);
+
+        let response = query(&context, "www.example.com", "10.0.0.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.header.recursion_available);
+        assert_eq!(response.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_zone_acl() {
+        // Public zones are served to clients that may not query otherwise
+        let context = server(AccessControl {
+            query: Acl::parse(&["none"]).unwrap(),
+            zones: vec![("example.com".to_string(), Acl::any())],
+            ..AccessControl::default()
+        });
+        let response = query(&context, "www.example.com", "203.0.113.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.header.authoritative_answer);
+        assert!(!response.header.recursion_available);
+        let response = query(&context, "www.example.org", "203.0.113.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+
+        // And private ones are kept from clients that may query otherwise
+        let context = server(AccessControl {
+            zones: vec![("example.com".to_string(), Acl::private())],
+            ..AccessControl::default()
+        });
+        let response = query(&context, "www.example.com", "203.0.113.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+    }
+
+    #[test]
+    fn test_transport_acl() {
+        let context = server(AccessControl {
+            udp: TransportRules {
+                recursion: Some(Acl::parse(&["none"]).unwrap()),
+                ..TransportRules::default()
+            },
+            ..AccessControl::default()
+        });
+
+        let response = query(&context, "www.example.org", "10.0.0.1", Transport::Udp);
+        assert_eq!(response.header.rescode, ResultCode::REFUSED);
+        assert!(!response.header.recursion_available);
+
+        let response = query(&context, "www.example.com", "10.0.0.1", Transport::Tcp);
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.header.recursion_available);
+    }
+
+    // An upstream that gives an authoritative answer to the next query
+    fn upstream() -> (SocketAddr, thread::JoinHandle<()>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let mut raw = [0; 512];
+            let (len, src) = socket.recv_from(&mut raw).unwrap();
+            let mut packet =
+                DnsPacket::from_buffer(&mut BytePacketBuffer::from_slice(&raw[..len])).unwrap();
+            packet.header.response = true;
+            packet.header.authoritative_answer = true;
+            packet.answers.push(DnsRecord::A {
+                domain: packet.questions[0].name.clone(),
+                addr: "192.0.2.1".parse().unwrap(),
+                ttl: 300,
+            });
+
+            let mut buffer = BytePacketBuffer::new();
+            packet.write(&mut buffer).unwrap();
+            socket.send_to(buffer.as_bytes(), src).unwrap();
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn test_relayed_answers_are_not_authoritative() {
+        let (addr, upstream) = upstream();
+        let context = ServerContext {
+            mode: Mode::Forwarding,
+            forwarder: Some(Forwarder::new(vec![addr], Selection::RoundRobin)),
+            ..server(AccessControl::default())
+        };
+
+        let response = query(&context, "www.example.org", "10.0.0.1", Transport::Udp);
+        upstream.join().unwrap();
+        assert_eq!(response.answers.len(), 1);
+        assert!(!response.header.authoritative_answer);
+
+        // Nor when the same answer comes from the cache
+        let response = query(&context, "www.example.org", "10.0.0.1", Transport::Udp);
+        assert_eq!(response.answers.len(), 1);
+        assert!(!response.header.authoritative_answer);
+
+        let response = query(&context, "www.example.com", "10.0.0.1", Transport::Udp);
+        assert!(response.header.authoritative_answer);
+    }
+
+    #[test]
+    fn test_no_recursion_desired() {
+        let context = server(AccessControl::default());
+        let client = "10.0.0.1".parse().unwrap();
+        let norec = |name| handle_query(request(name, false), &context, client, Transport::Udp);
+
+        // With nothing cached there is nothing to answer with, and nothing is
+        // sent upstream
+        let response = norec("www.example.org");
+        assert_eq!(response.header.rescode, ResultCode::SERVFAIL);
+        assert!(!response.header.recursion_desired);
+        assert!(response.header.recursion_available);
+
+        // What we know of the zone makes a referral
+        context.cache.store(&[
+            DnsRecord::NS {
+                domain: "example.org".to_string(),
+                host: "ns1.example.org".to_string(),
+                ttl: 300,
+            },
+            DnsRecord::A {
+                domain: "ns1.example.org".to_string(),
+                addr: "192.0.2.53".parse().unwrap(),
+                ttl: 300,
+            },
+        ]);
+        let response = norec("www.example.org");
+        assert_eq!(response.header.rescode, ResultCode::NOERROR);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.resources.len(), 1);
+
+        context.cache.store(&[DnsRecord::A {
+            domain: "www.example.org".to_string(),
+            addr: "192.0.2.1".parse().unwrap(),
+            ttl: 300,
+        }]);
+        assert_eq!(norec("www.example.org").answers.len(), 1);
+
+        let response = norec("www.example.com");
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.answers.len(), 1);
+    }
+}
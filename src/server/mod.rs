@@ -0,0 +1,6 @@
+pub mod acl;
+pub mod config;
+pub mod context;
+pub mod handler;
+pub mod tcp;
+pub mod udp;
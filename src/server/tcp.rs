@@ -0,0 +1,133 @@
+use std::io::ErrorKind;
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::buffer::buffer::BytePacketBuffer;
+use crate::dns::dns_packet::DnsPacket;
+use crate::utils::tcp::{read_message, write_message};
+use crate::utils::thread_pool::ThreadPool;
+use crate::utils::types::Result;
+use crate::{log_error, log_warn};
+
+use super::acl::Transport;
+use super::context::ServerContext;
+use super::handler::handle_query;
+
+/// Connections that haven't sent a query for this long are closed, so idle
+/// clients can't hold on to server resources (RFC 7766 section 6.2.3)
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Clients that don't read their answers for this long are dropped, rather
+/// than leaving a worker of the pool stuck writing to them
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most TCP connections we keep open at once. Further connections are
+/// closed straight away.
+const MAX_CONNECTIONS: usize = 128;
+
+/// Accept TCP connections forever, reading each on its own thread. The
+/// queries themselves are answered by the shared pool.
+pub fn serve_tcp(listener: TcpListener, context: Arc<ServerContext>, pool: Arc<ThreadPool>) {
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log_error!("Failed to accept TCP connection: {}", e);
+                continue;
+            }
+        };
+
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            log_warn!("Too many TCP connections, closing new one");
+            continue;
+        }
+
+        let context = context.clone();
+        let pool = pool.clone();
+        let connections = connections.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_tcp_connection(stream, context, &pool) {
+                log_error!("An error occurred on a TCP connection: {}", e);
+            }
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Serve every query sent on a single connection. Clients may pipeline
+/// several queries without waiting for the answers, so each query is queued
+/// on the pool as soon as it is read and answered when it is ready, which may
+/// be out of order. The connection is closed once the client goes quiet.
+fn handle_tcp_connection(
+    stream: TcpStream,
+    context: Arc<ServerContext>,
+    pool: &ThreadPool,
+) -> Result<()> {
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let client = stream.peer_addr()?.ip();
+
+    let mut reader = stream.try_clone()?;
+    let writer = Arc::new(Mutex::new(stream));
+
+    loop {
+        let message = match read_message(&mut reader) {
+            Ok(message) => message,
+            Err(e) => {
+                // The client closing the connection or going idle is the
+                // normal way for a connection to end
+                if let Some(io) = e.downcast_ref::<std::io::Error>() {
+                    if matches!(
+                        io.kind(),
+                        ErrorKind::UnexpectedEof | ErrorKind::WouldBlock | ErrorKind::TimedOut
+                    ) {
+                        return Ok(());
+                    }
+                }
+                return Err(e);
+            }
+        };
+
+        // Waiting for room in the pool stops us reading further queries from
+        // this client until the server catches up
+        let writer = writer.clone();
+        let context = context.clone();
+        pool.execute(move || {
+            if let Err(e) = handle_tcp_query(&message, client, &writer, &context) {
+                log_error!("An error occurred: {}", e);
+            }
+        })?;
+    }
+}
+
+/// Answer a single query received over TCP
+fn handle_tcp_query(
+    message: &[u8],
+    client: IpAddr,
+    writer: &Mutex<TcpStream>,
+    context: &ServerContext,
+) -> Result<()> {
+    let mut req_buffer = BytePacketBuffer::from_slice(message);
+    let request = DnsPacket::from_buffer(&mut req_buffer)?;
+
+    let packet = handle_query(request, context, client, Transport::Tcp);
+
+    // Responses over TCP aren't limited by the UDP payload size
+    let mut res_buffer = BytePacketBuffer::growable();
+    packet.write(&mut res_buffer)?;
+
+    let mut stream = writer.lock().map_err(|_| "TCP writer lock poisoned")?;
+    let result = write_message(&mut *stream, res_buffer.as_bytes());
+    if result.is_err() {
+        // Part of the answer may have gone out, so the stream can't be used
+        // for another one. Closing it also ends the reading side.
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+    result
+}
@@ -0,0 +1,80 @@
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+
+use crate::buffer::buffer::BytePacketBuffer;
+use crate::dns::dns_packet::{DnsPacket, MAX_UDP_PAYLOAD_SIZE};
+use crate::utils::thread_pool::ThreadPool;
+use crate::utils::types::Result;
+use crate::{log_debug, log_error, log_warn};
+
+use super::acl::Transport;
+use super::context::ServerContext;
+use super::handler::handle_query;
+
+/// Receive datagrams forever, handing each one to the pool so that a slow
+/// resolution doesn't hold up other clients. When every worker is busy and
+/// the queue is full, further queries are dropped and the clients will retry.
+pub fn serve_udp(socket: Arc<UdpSocket>, context: Arc<ServerContext>, pool: Arc<ThreadPool>) {
+    loop {
+        // With a socket ready, we can go ahead and read a packet. This will
+        // block until one is received.
+        let mut raw = [0; MAX_UDP_PAYLOAD_SIZE as usize];
+
+        // The `recv_from` function will write the data into the provided buffer,
+        // and return the length of the data read as well as the source address.
+        // We need the length so that only the bytes we actually received are
+        // parsed, and the source in order to send our reply later on.
+        let (len, src) = match socket.recv_from(&mut raw) {
+            Ok(received) => received,
+            Err(e) => {
+                log_error!("An error occurred: {}", e);
+                continue;
+            }
+        };
+        let data = raw[..len].to_vec();
+
+        let socket = socket.clone();
+        let context = context.clone();
+        let job = move || {
+            if let Err(e) = handle_udp_query(&socket, &data, src, &context) {
+                log_error!("An error occurred: {}", e);
+            }
+        };
+
+        if let Err(e) = pool.try_execute(job) {
+            log_warn!("Dropping query from {}: {}", src, e);
+        }
+    }
+}
+
+/// Handle a single incoming packet, sending the response back to `src` on
+/// the socket it arrived on
+pub fn handle_udp_query(
+    socket: &UdpSocket,
+    data: &[u8],
+    src: SocketAddr,
+    context: &ServerContext,
+) -> Result<()> {
+    // Next, `DnsPacket::from_buffer` is used to parse the raw bytes into
+    // a `DnsPacket`.
+    let mut req_buffer = BytePacketBuffer::from_slice(data);
+    let request = DnsPacket::from_buffer(&mut req_buffer)?;
+
+    // If the client speaks EDNS(0), the response may be as large as the
+    // client says it can handle, up to our own limit.
+    let payload_size = request.max_udp_payload_size().min(MAX_UDP_PAYLOAD_SIZE);
+
+    let packet = handle_query(request, context, src.ip(), Transport::Udp);
+
+    // encode our response and send it back. If it's too large for the client,
+    // as much as fits is sent with the TC bit set, and the client is expected
+    // to ask again over TCP.
+    let mut res_buffer = BytePacketBuffer::with_capacity(payload_size as usize);
+    if packet.write_truncated(&mut res_buffer)? {
+        log_debug!("Response truncated to {} bytes", res_buffer.len());
+    }
+
+    socket.send_to(res_buffer.as_bytes(), src)?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// How much is logged, from nothing at all to every step of a resolution
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off = 0,
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LogLevel, String> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(LogLevel::Off),
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!(
+                "unknown log level {:?}, expected off, error, warn, info or debug",
+                s
+            )),
+        }
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+/// Set the level for the whole process
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Whether messages of the given level are logged
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Log something that went wrong, to stderr
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {
+        if $crate::utils::log::enabled($crate::utils::log::LogLevel::Error) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Log something that may need attention, to stderr
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        if $crate::utils::log::enabled($crate::utils::log::LogLevel::Warn) {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Log what the server is doing, to stdout
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {
+        if $crate::utils::log::enabled($crate::utils::log::LogLevel::Info) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Log the details of how it's doing it, to stdout
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        if $crate::utils::log::enabled($crate::utils::log::LogLevel::Debug) {
+            println!($($arg)*);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!("DEBUG".parse::<LogLevel>(), Ok(LogLevel::Debug));
+        assert!("verbose".parse::<LogLevel>().is_err());
+        assert!(LogLevel::Error < LogLevel::Info);
+    }
+}
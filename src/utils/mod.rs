@@ -1 +1,5 @@
+pub mod log;
+pub mod random;
+pub mod tcp;
+pub mod thread_pool;
 pub mod types;
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::OnceLock;
+
+/// The operating system's random source, opened on first use
+static URANDOM: OnceLock<File> = OnceLock::new();
+
+/// An unpredictable 64 bit number, read from the operating system's random
+/// source. Transaction ids and source ports have to be drawn from a proper
+/// CSPRNG for them to protect against forged responses (RFC 5452).
+pub fn random_u64() -> u64 {
+    let mut source: &File =
+        URANDOM.get_or_init(|| File::open("/dev/urandom").expect("failed to open /dev/urandom"));
+
+    let mut bytes = [0; 8];
+    source
+        .read_exact(&mut bytes)
+        .expect("failed to read /dev/urandom");
+    u64::from_ne_bytes(bytes)
+}
+
+/// An unpredictable 16 bit number, e.g. for a transaction id
+pub fn random_u16() -> u16 {
+    random_u64() as u16
+}
+
+/// An unpredictable number in the range `low..high`
+pub fn random_range(low: u64, high: u64) -> u64 {
+    low + random_u64() % (high - low)
+}
+
+/// Put the items of a slice in a random order
+pub fn shuffle<T>(items: &mut [T]) {
+    for i in (1..items.len()).rev() {
+        let j = random_range(0, i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_values_differ() {
+        let values: Vec<u64> = (0..16).map(|_| random_u64()).collect();
+        for (i, value) in values.iter().enumerate() {
+            assert!(!values[i + 1..].contains(value));
+        }
+    }
+
+    #[test]
+    fn test_random_range() {
+        for _ in 0..100 {
+            let value = random_range(1024, 65536);
+            assert!((1024..65536).contains(&value));
+        }
+    }
+}
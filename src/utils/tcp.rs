@@ -0,0 +1,49 @@
+use std::io::{Read, Write};
+
+use crate::utils::types::Result;
+
+/// Over TCP every DNS message is preceded by its length as a two byte
+/// integer (RFC 1035 section 4.2.2). This reads one such message.
+pub fn read_message<R: Read>(stream: &mut R) -> Result<Vec<u8>> {
+    let mut len_bytes = [0; 2];
+    stream.read_exact(&mut len_bytes)?;
+    let len = u16::from_be_bytes(len_bytes) as usize;
+
+    let mut message = vec![0; len];
+    stream.read_exact(&mut message)?;
+
+    Ok(message)
+}
+
+/// Write a single DNS message with its two byte length prefix. Both are
+/// written in one go so the message isn't split over several segments.
+pub fn write_message<W: Write>(stream: &mut W, message: &[u8]) -> Result<()> {
+    if message.len() > u16::MAX as usize {
+        return Err("Message is too large to be sent over TCP".into());
+    }
+
+    let mut framed = Vec::with_capacity(message.len() + 2);
+    framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    framed.extend_from_slice(message);
+    stream.write_all(&framed)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing_round_trip() {
+        let mut stream = Vec::new();
+        write_message(&mut stream, &[1, 2, 3]).unwrap();
+        write_message(&mut stream, &[4, 5]).unwrap();
+        assert_eq!(stream[..2], [0, 3]);
+
+        let mut reader = &stream[..];
+        assert_eq!(read_message(&mut reader).unwrap(), vec![1, 2, 3]);
+        assert_eq!(read_message(&mut reader).unwrap(), vec![4, 5]);
+        assert!(read_message(&mut reader).is_err());
+    }
+}
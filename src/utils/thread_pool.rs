@@ -0,0 +1,109 @@
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::utils::types::Result;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads fed from a bounded queue. The number of
+/// workers caps how many jobs run at once, and the queue caps how many may be
+/// waiting for a worker.
+pub struct ThreadPool {
+    sender: SyncSender<Job>,
+}
+
+impl ThreadPool {
+    pub fn new(workers: usize, queue_size: usize) -> ThreadPool {
+        let (sender, receiver) = sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..workers.max(1) {
+            let receiver = receiver.clone();
+            thread::spawn(move || Self::work(&receiver));
+        }
+
+        ThreadPool { sender }
+    }
+
+    // Run jobs until the pool is dropped and the queue is closed
+    fn work(receiver: &Mutex<Receiver<Job>>) {
+        loop {
+            // The lock is only held while waiting for the next job, not while
+            // running it
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+
+            match job {
+                Ok(job) => job(),
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Queue a job, waiting for room in the queue if it is full
+    pub fn execute<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender
+            .send(Box::new(job))
+            .map_err(|_| "Thread pool has shut down".into())
+    }
+
+    /// Queue a job, failing straight away if the queue is full
+    pub fn try_execute<F>(&self, job: F) -> Result<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.sender.try_send(Box::new(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err("Thread pool queue is full".into()),
+            Err(TrySendError::Disconnected(_)) => Err("Thread pool has shut down".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_jobs_run_on_workers() {
+        let pool = ThreadPool::new(4, 16);
+        let (tx, rx) = channel();
+
+        for i in 0..8 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+
+        let mut results: Vec<i32> = rx.iter().take(8).collect();
+        results.sort();
+        assert_eq!(results, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_try_execute_fails_when_full() {
+        let pool = ThreadPool::new(1, 1);
+        let (block_tx, block_rx) = channel::<()>();
+        let (started_tx, started_rx) = channel();
+
+        // Occupy the only worker until we say so
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            block_rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        // One job fits in the queue, the next one doesn't
+        pool.try_execute(|| {}).unwrap();
+        assert!(pool.try_execute(|| {}).is_err());
+
+        block_tx.send(()).unwrap();
+    }
+}
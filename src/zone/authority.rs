@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use crate::dns::dns_header::ResultCode;
+use crate::dns::dns_packet::DnsPacket;
+use crate::dns::dns_record::DnsRecord;
+use crate::dns::domain_name::is_subdomain;
+use crate::dns::query_type::QueryType;
+use crate::utils::types::Result;
+
+use super::zone_file::{parse_zone_file, write_zone};
+
+/// Upper bound on the number of CNAMEs followed within a zone
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// Zone holds the records of a zone we are authoritative for
+#[derive(Debug)]
+pub struct Zone {
+    /// The name at the top of the zone, in lowercase
+    pub origin: String,
+    soa: DnsRecord,
+    // Records grouped by their owner name, in lowercase
+    records: HashMap<String, Vec<DnsRecord>>,
+    // Every name that exists in the zone, which includes the names that own
+    // no records but have names below them that do
+    names: HashSet<String>,
+}
+
+impl Zone {
+    /// Build a zone from its records, which have to include the SOA record
+    /// of the zone and may not have owners outside of it
+    pub fn new(origin: &str, records: Vec<DnsRecord>) -> Result<Zone> {
+        let origin = origin.trim_end_matches('.').to_lowercase();
+
+        let mut soa = None;
+        let mut by_name: HashMap<String, Vec<DnsRecord>> = HashMap::new();
+        let mut names = HashSet::new();
+
+        for record in records {
+            let owner = record.domain().to_lowercase();
+            if !is_subdomain(&owner, &origin) {
+                return Err(format!("{} is outside of zone {}", owner, origin).into());
+            }
+
+            if let DnsRecord::SOA { .. } = record {
+                if owner != origin {
+                    return Err(format!(
+                        "SOA record for {} isn't at the top of zone {}",
+                        owner, origin
+                    )
+                    .into());
+                }
+                if soa.is_some() {
+                    return Err(format!("zone {} has more than one SOA record", origin).into());
+                }
+                soa = Some(record.clone());
+            }
+
+            // The owner exists, and so does every name between it and the
+            // top of the zone
+            let mut name = owner.as_str();
+            while names.insert(name.to_string()) && name != origin {
+                name = match name.find('.') {
+                    Some(idx) => &name[idx + 1..],
+                    None => "",
+                };
+            }
+
+            by_name.entry(owner).or_default().push(record);
+        }
+
+        let soa = soa.ok_or_else(|| format!("zone {} has no SOA record", origin))?;
+
+        Ok(Zone {
+            origin,
+            soa,
+            records: by_name,
+            names,
+        })
+    }
+
+    /// Load a zone from a zone file
+    pub fn load<P: AsRef<Path>>(origin: &str, path: P) -> Result<Zone> {
+        Zone::new(origin, parse_zone_file(path, origin)?)
+    }
+
+    /// Write the zone out as a zone file
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let records: Vec<DnsRecord> = self.records.values().flatten().cloned().collect();
+        write_zone(out, &self.origin, &records)
+    }
+
+    fn get(&self, name: &str, qtype: QueryType) -> Vec<DnsRecord> {
+        self.records
+            .get(name)
+            .map(|records| {
+                records
+                    .iter()
+                    .filter(|record| record.query_type() == qtype)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The SOA record to send with negative answers, whose TTL is the time
+    /// resolvers may cache them for (RFC 2308)
+    fn negative_soa(&self) -> DnsRecord {
+        let mut soa = self.soa.clone();
+        if let DnsRecord::SOA { ttl, minimum, .. } = self.soa {
+            soa.set_ttl(ttl.min(minimum));
+        }
+        soa
+    }
+
+    // The highest delegation point between the top of the zone and `name`,
+    // if there is one. The top of the zone has NS records too, but those
+    // are ours.
+    fn find_delegation<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let labels: Vec<&str> = name.split('.').filter(|label| !label.is_empty()).collect();
+        let origin_labels = self
+            .origin
+            .split('.')
+            .filter(|label| !label.is_empty())
+            .count();
+
+        (0..labels.len().saturating_sub(origin_labels))
+            .rev()
+            .map(|idx| {
+                let start: usize = labels[..idx].iter().map(|label| label.len() + 1).sum();
+                &name[start..]
+            })
+            .find(|cut| !self.get(cut, QueryType::NS).is_empty())
+    }
+
+    // The records owned by `name`, synthesized from a wildcard if the name
+    // doesn't exist itself (RFC 4592). `None` means the name doesn't exist.
+    fn find_records(&self, name: &str) -> Option<Vec<DnsRecord>> {
+        if self.names.contains(name) {
+            return Some(self.records.get(name).cloned().unwrap_or_default());
+        }
+
+        // A wildcard only applies below the closest name that does exist
+        let mut encloser = name;
+        while !self.names.contains(encloser) {
+            if encloser.is_empty() {
+                return None;
+            }
+            encloser = match encloser.find('.') {
+                Some(idx) => &encloser[idx + 1..],
+                None => "",
+            };
+        }
+
+        let wildcard = if encloser.is_empty() {
+            "*".to_string()
+        } else {
+            format!("*.{}", encloser)
+        };
+
+        self.records.get(&wildcard).map(|records| {
+            records
+                .iter()
+                .cloned()
+                .map(|mut record| {
+                    record.set_domain(name);
+                    record
+                })
+                .collect()
+        })
+    }
+
+    // The NS records of a delegation along with the addresses of the name
+    // servers that we have, which resolvers need when the servers are inside
+    // the delegated zone
+    fn referral(&self, cut: &str, packet: &mut DnsPacket) {
+        let ns = self.get(cut, QueryType::NS);
+
+        for record in &ns {
+            if let DnsRecord::NS { host, .. } = record {
+                let host = host.to_lowercase();
+                if is_subdomain(&host, &self.origin) {
+                    packet.resources.extend(self.get(&host, QueryType::A));
+                    packet.resources.extend(self.get(&host, QueryType::AAAA));
+                }
+            }
+        }
+        packet.authorities.extend(ns);
+    }
+
+    /// Answer a question for a name in this zone. Names at or below a
+    /// delegation get a referral, and answers for names that exist are
+    /// authoritative, whether they hold records of the type asked for or not.
+    pub fn answer(&self, qname: &str, qtype: QueryType) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        let mut name = qname.to_lowercase();
+
+        for _ in 0..MAX_CNAME_CHAIN {
+            if let Some(cut) = self.find_delegation(&name) {
+                self.referral(cut, &mut packet);
+                // What we know about the names of the chain so far is ours
+                packet.header.authoritative_answer = !packet.answers.is_empty();
+                return packet;
+            }
+
+            packet.header.authoritative_answer = true;
+
+            let records = match self.find_records(&name) {
+                Some(records) => records,
+                None => {
+                    packet.header.rescode = ResultCode::NXDOMAIN;
+                    packet.authorities.push(self.negative_soa());
+                    return packet;
+                }
+            };
+
+            let answers: Vec<DnsRecord> = records
+                .iter()
+                .filter(|record| record.query_type() == qtype)
+                .cloned()
+                .collect();
+            if !answers.is_empty() {
+                packet.answers.extend(answers);
+                return packet;
+            }
+
+            // An alias is followed for as long as it stays in this zone, and
+            // the rest of the chain is left to the client
+            let target = records.iter().find_map(|record| match record {
+                DnsRecord::CNAME { host, .. } => Some(host.to_lowercase()),
+                _ => None,
+            });
+            match target {
+                Some(target) if qtype != QueryType::CNAME => {
+                    packet.answers.extend(
+                        records
+                            .into_iter()
+                            .filter(|record| record.query_type() == QueryType::CNAME),
+                    );
+                    if !is_subdomain(&target, &self.origin) {
+                        return packet;
+                    }
+                    name = target;
+                }
+                // The name exists, but has no records of the type asked for
+                _ => {
+                    packet.authorities.push(self.negative_soa());
+                    return packet;
+                }
+            }
+        }
+
+        packet
+    }
+}
+
+/// Authority holds the zones the server answers for itself
+#[derive(Debug, Default)]
+pub struct Authority {
+    zones: Vec<Zone>,
+}
+
+impl Authority {
+    pub fn new() -> Authority {
+        Authority { zones: Vec::new() }
+    }
+
+    pub fn add_zone(&mut self, zone: Zone) {
+        self.zones.push(zone);
+    }
+
+    /// The most specific zone `qname` falls in, if any
+    pub fn find_zone(&self, qname: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .filter(|zone| is_subdomain(qname, &zone.origin))
+            .max_by_key(|zone| zone.origin.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::zone_file::parse_zone;
+
+    fn zone() -> Zone {
+        let text = "
+$TTL 3600
+@           SOA ns1 hostmaster 1 7200 3600 1209600 300
+            NS  ns1
+ns1         A   10.0.0.1
+www         A   10.0.0.2
+alias       CNAME www
+outside     CNAME www.example.org.
+*.wild      TXT \"wildcard\"
+a.b.c       A   10.0.0.3
+sub         NS  ns.sub
+ns.sub      A   10.0.0.4
+";
+        Zone::new("example.com", parse_zone(text, "example.com").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_answer() {
+        let packet = zone().answer("WWW.example.com", QueryType::A);
+        assert!(packet.header.authoritative_answer);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert_eq!(packet.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_nxdomain_and_nodata() {
+        let zone = zone();
+
+        let packet = zone.answer("nope.example.com", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NXDOMAIN);
+        assert!(packet.header.authoritative_answer);
+        assert_eq!(packet.authorities[0].ttl(), 300);
+
+        let packet = zone.answer("www.example.com", QueryType::MX);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities.len(), 1);
+
+        // b.c exists, since there's a name below it
+        let packet = zone.answer("b.c.example.com", QueryType::A);
+        assert_eq!(packet.header.rescode, ResultCode::NOERROR);
+    }
+
+    #[test]
+    fn test_cname() {
+        let zone = zone();
+
+        let packet = zone.answer("alias.example.com", QueryType::A);
+        assert_eq!(packet.answers.len(), 2);
+
+        let packet = zone.answer("outside.example.com", QueryType::A);
+        assert_eq!(packet.answers.len(), 1);
+    }
+
+    #[test]
+    fn test_referral() {
+        let packet = zone().answer("www.sub.example.com", QueryType::A);
+        assert!(!packet.header.authoritative_answer);
+        assert!(packet.answers.is_empty());
+        assert_eq!(packet.authorities.len(), 1);
+        assert_eq!(packet.resources.len(), 1);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let zone = zone();
+
+        let packet = zone.answer("anything.wild.example.com", QueryType::TXT);
+        assert_eq!(packet.answers[0].domain(), "anything.wild.example.com");
+
+        // Wildcards don't apply to names that exist
+        let packet = zone.answer("www.example.com", QueryType::TXT);
+        assert!(packet.answers.is_empty());
+    }
+
+    #[test]
+    fn test_zone_validation() {
+        let soa = DnsRecord::SOA {
+            domain: "example.com".to_string(),
+            mname: "ns1.example.com".to_string(),
+            rname: "hostmaster.example.com".to_string(),
+            serial: 1,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+            ttl: 3600,
+        };
+        let outside = DnsRecord::A {
+            domain: "www.example.org".to_string(),
+            addr: "10.0.0.1".parse().unwrap(),
+            ttl: 3600,
+        };
+
+        assert!(Zone::new("example.com", vec![]).is_err());
+        assert!(Zone::new("example.com", vec![soa.clone(), outside]).is_err());
+
+        let mut authority = Authority::new();
+        authority.add_zone(Zone::new("example.com", vec![soa]).unwrap());
+        assert!(authority.find_zone("www.example.com").is_some());
+        assert!(authority.find_zone("www.evilexample.com").is_none());
+    }
+}
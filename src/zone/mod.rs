@@ -0,0 +1,2 @@
+pub mod authority;
+pub mod zone_file;
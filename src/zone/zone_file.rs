@@ -0,0 +1,730 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::dns::dns_record::DnsRecord;
+use crate::dns::domain_name::escape_name;
+use crate::utils::types::Result;
+
+/// How deeply `$INCLUDE` directives may nest, which stops files that
+/// include each other
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// A word of a zone file, with quoted strings kept whole
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// A single directive or record, which parentheses may spread over several
+/// lines
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    // An entry starting with a blank has no owner of its own, and belongs to
+    // the owner of the previous record
+    leading_blank: bool,
+    tokens: Vec<Token>,
+}
+
+/// ZoneParser turns zone files in the master file format of RFC 1035 into
+/// records. Names that don't end with a dot are relative to the origin.
+struct ZoneParser {
+    origin: String,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<String>,
+    records: Vec<DnsRecord>,
+}
+
+/// Read the records of a zone from a file. `origin` is the initial origin,
+/// normally the name of the zone, which `$ORIGIN` directives may change.
+pub fn parse_zone_file<P: AsRef<Path>>(path: P, origin: &str) -> Result<Vec<DnsRecord>> {
+    let mut parser = ZoneParser::new(origin);
+    parser.parse_file(path.as_ref(), 0)?;
+    Ok(parser.records)
+}
+
+/// Read the records of a zone from text. Included files are looked up
+/// relative to the working directory.
+pub fn parse_zone(text: &str, origin: &str) -> Result<Vec<DnsRecord>> {
+    let mut parser = ZoneParser::new(origin);
+    parser.parse(text, Path::new("."), "<zone>", 0)?;
+    Ok(parser.records)
+}
+
+impl ZoneParser {
+    fn new(origin: &str) -> ZoneParser {
+        ZoneParser {
+            origin: normalize(origin),
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            records: Vec::new(),
+        }
+    }
+
+    fn parse_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(format!("{}: includes nest too deeply", path.display()).into());
+        }
+
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        self.parse(&text, dir, &path.display().to_string(), depth)
+    }
+
+    fn parse(&mut self, text: &str, dir: &Path, file: &str, depth: usize) -> Result<()> {
+        for entry in tokenize(text).map_err(|e| format!("{}:{}", file, e))? {
+            self.parse_entry(entry, dir, depth)
+                .map_err(|e| format!("{}:{}", file, e))?;
+        }
+        Ok(())
+    }
+
+    fn parse_entry(&mut self, entry: Entry, dir: &Path, depth: usize) -> Result<()> {
+        let line = entry.line;
+        let error = |msg: String| format!("{}: {}", line, msg);
+
+        let mut tokens = entry.tokens.into_iter().peekable();
+
+        // Directives
+        match tokens.peek().map(|token| token.text.to_uppercase()) {
+            Some(ref directive) if directive == "$ORIGIN" => {
+                tokens.next();
+                let origin = tokens
+                    .next()
+                    .ok_or_else(|| error("$ORIGIN needs a name".to_string()))?;
+                self.origin = self
+                    .absolute(&origin.text)
+                    .map_err(|e| error(e.to_string()))?;
+                return Ok(());
+            }
+            Some(ref directive) if directive == "$TTL" => {
+                tokens.next();
+                let ttl = tokens
+                    .next()
+                    .and_then(|token| parse_ttl(&token.text))
+                    .ok_or_else(|| error("$TTL needs a time to live".to_string()))?;
+                self.default_ttl = Some(ttl);
+                return Ok(());
+            }
+            Some(ref directive) if directive == "$INCLUDE" => {
+                tokens.next();
+                let file = tokens
+                    .next()
+                    .ok_or_else(|| error("$INCLUDE needs a file name".to_string()))?;
+
+                // The included file may have an origin of its own, but the
+                // origin of this file doesn't change either way
+                let saved_origin = self.origin.clone();
+                if let Some(origin) = tokens.next() {
+                    self.origin = self
+                        .absolute(&origin.text)
+                        .map_err(|e| error(e.to_string()))?;
+                }
+                let file = String::from_utf8_lossy(&unescape(&file.text)).into_owned();
+                let result = self.parse_file(&dir.join(file), depth + 1);
+                self.origin = saved_origin;
+                return result.map_err(|e| error(e.to_string()).into());
+            }
+            Some(ref directive) if directive.starts_with('$') => {
+                return Err(error(format!("unknown directive {}", directive)).into());
+            }
+            _ => {}
+        }
+
+        let owner = if entry.leading_blank {
+            self.last_owner
+                .clone()
+                .ok_or_else(|| error("record without an owner".to_string()))?
+        } else {
+            let owner = tokens.next().map(|token| token.text).unwrap_or_default();
+            self.absolute(&owner).map_err(|e| error(e.to_string()))?
+        };
+        self.last_owner = Some(owner.clone());
+
+        // The TTL and class may come in either order, and both are optional
+        let mut ttl = None;
+        let mut rtype = None;
+        for token in tokens.by_ref() {
+            let text = token.text.to_uppercase();
+            if ttl.is_none() && text.starts_with(|c: char| c.is_ascii_digit()) {
+                ttl = Some(parse_ttl(&text).ok_or_else(|| error(format!("invalid TTL {}", text)))?);
+            } else if text == "IN" {
+                continue;
+            } else if matches!(text.as_str(), "CH" | "HS" | "CS") {
+                return Err(error(format!("class {} isn't supported", text)).into());
+            } else {
+                rtype = Some(text);
+                break;
+            }
+        }
+        let rtype = rtype.ok_or_else(|| error("missing record type".to_string()))?;
+
+        // Without a TTL of its own, a record gets the one from `$TTL`, or else
+        // the one of the previous record
+        let ttl = match ttl.or(self.default_ttl).or(self.last_ttl) {
+            Some(ttl) => ttl,
+            None => return Err(error("no TTL given and no $TTL set".to_string()).into()),
+        };
+        self.last_ttl = Some(ttl);
+
+        let rdata: Vec<Token> = tokens.collect();
+        let record = self
+            .parse_record(owner, ttl, &rtype, &rdata)
+            .map_err(|e| error(format!("{} record: {}", rtype, e)))?;
+        self.records.push(record);
+
+        Ok(())
+    }
+
+    fn parse_record(
+        &self,
+        domain: String,
+        ttl: u32,
+        rtype: &str,
+        rdata: &[Token],
+    ) -> Result<DnsRecord> {
+        let field = |idx: usize| -> Result<&str> {
+            rdata
+                .get(idx)
+                .map(|token| token.text.as_str())
+                .ok_or_else(|| "missing data".into())
+        };
+        let expect = |count: usize| -> Result<()> {
+            if rdata.len() > count {
+                return Err(format!("unexpected {}", rdata[count].text).into());
+            }
+            Ok(())
+        };
+        let number = |idx: usize| -> Result<u16> {
+            let text = field(idx)?;
+            text.parse()
+                .map_err(|_| format!("invalid number {}", text).into())
+        };
+        let time = |idx: usize| -> Result<u32> {
+            let text = field(idx)?;
+            parse_ttl(text).ok_or_else(|| format!("invalid time {}", text).into())
+        };
+
+        // Records of types we don't know are written in the generic form of
+        // RFC 3597, e.g. `TYPE65534 \# 4 0a000001`
+        if let Some(num) = rtype.strip_prefix("TYPE") {
+            let qtype: u16 = num.parse().map_err(|_| format!("invalid type {}", rtype))?;
+            return Ok(DnsRecord::UNKNOWN {
+                domain,
+                qtype,
+                class: 1,
+                data: parse_generic_rdata(rdata)?,
+                ttl,
+            });
+        }
+
+        let record = match rtype {
+            "A" => DnsRecord::A {
+                domain,
+                addr: field(0)?
+                    .parse()
+                    .map_err(|_| format!("invalid address {}", field(0).unwrap_or("")))?,
+                ttl,
+            },
+            "AAAA" => DnsRecord::AAAA {
+                domain,
+                addr: field(0)?
+                    .parse()
+                    .map_err(|_| format!("invalid address {}", field(0).unwrap_or("")))?,
+                ttl,
+            },
+            "NS" => DnsRecord::NS {
+                domain,
+                host: self.absolute(field(0)?)?,
+                ttl,
+            },
+            "CNAME" => DnsRecord::CNAME {
+                domain,
+                host: self.absolute(field(0)?)?,
+                ttl,
+            },
+            "PTR" => DnsRecord::PTR {
+                domain,
+                host: self.absolute(field(0)?)?,
+                ttl,
+            },
+            "MX" => DnsRecord::MX {
+                domain,
+                priority: number(0)?,
+                host: self.absolute(field(1)?)?,
+                ttl,
+            },
+            "SRV" => DnsRecord::SRV {
+                domain,
+                priority: number(0)?,
+                weight: number(1)?,
+                port: number(2)?,
+                target: self.absolute(field(3)?)?,
+                ttl,
+            },
+            "SOA" => DnsRecord::SOA {
+                domain,
+                mname: self.absolute(field(0)?)?,
+                rname: self.absolute(field(1)?)?,
+                serial: field(2)?
+                    .parse()
+                    .map_err(|_| format!("invalid serial {}", field(2).unwrap_or("")))?,
+                refresh: time(3)?,
+                retry: time(4)?,
+                expire: time(5)?,
+                minimum: time(6)?,
+                ttl,
+            },
+            "TXT" => {
+                if rdata.is_empty() {
+                    return Err("missing data".into());
+                }
+                DnsRecord::TXT {
+                    domain,
+                    data: rdata.iter().map(|token| unescape(&token.text)).collect(),
+                    ttl,
+                }
+            }
+            _ => return Err("unsupported record type".into()),
+        };
+
+        let fields = match rtype {
+            "A" | "AAAA" | "NS" | "CNAME" | "PTR" => 1,
+            "MX" => 2,
+            "SRV" => 4,
+            "SOA" => 7,
+            _ => rdata.len(),
+        };
+        expect(fields)?;
+
+        // Quoted strings only make sense in TXT records
+        if rtype != "TXT" && rdata.iter().any(|token| token.quoted) {
+            return Err("unexpected quoted string".into());
+        }
+
+        Ok(record)
+    }
+
+    // Make a name absolute, stripping the trailing dot the way `read_qname`
+    // does. `@` stands for the origin itself, while `\@` is a label. Names
+    // are kept as dotted text, so a label can't hold a dot of its own.
+    fn absolute(&self, name: &str) -> Result<String> {
+        if name == "@" {
+            return Ok(self.origin.clone());
+        }
+        if has_escaped_dot(name) {
+            return Err(format!("escaped dot in {} isn't supported", name).into());
+        }
+        let unescaped =
+            String::from_utf8(unescape(name)).map_err(|_| format!("{} isn't valid UTF-8", name))?;
+
+        Ok(if unescaped.ends_with('.') {
+            normalize(&unescaped)
+        } else if self.origin.is_empty() {
+            unescaped
+        } else {
+            format!("{}.{}", unescaped, self.origin)
+        })
+    }
+}
+
+/// Write records as a zone file that `parse_zone_file` reads back. The SOA
+/// record comes first, as it has to, and the others follow in the order of
+/// their owner names, with names that share a parent kept together.
+pub fn write_zone<W: Write>(out: &mut W, origin: &str, records: &[DnsRecord]) -> Result<()> {
+    let mut sorted: Vec<&DnsRecord> = records.iter().collect();
+    sorted.sort_by_key(|record| {
+        let is_soa = matches!(record, DnsRecord::SOA { .. });
+        let labels: Vec<String> = record
+            .domain()
+            .to_lowercase()
+            .rsplit('.')
+            .map(str::to_string)
+            .collect();
+        (!is_soa, labels, record.query_type().to_num())
+    });
+
+    writeln!(out, "$ORIGIN {}", escape_name(origin))?;
+    for record in sorted {
+        writeln!(out, "{}", record)?;
+    }
+
+    Ok(())
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_string()
+}
+
+/// Parse a time to live, either in seconds or with the units BIND allows,
+/// as in `1h30m`
+pub fn parse_ttl(text: &str) -> Option<u32> {
+    if let Ok(seconds) = text.parse() {
+        return Some(seconds);
+    }
+
+    let mut total: u32 = 0;
+    let mut value: Option<u32> = None;
+    for c in text.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            value = Some(value.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(value.take()?.checked_mul(unit)?)?;
+    }
+
+    match value {
+        Some(_) => None,
+        None => Some(total),
+    }
+}
+
+// Parse the `\# <len> <hex>` generic form of rdata
+fn parse_generic_rdata(rdata: &[Token]) -> Result<Vec<u8>> {
+    if !rdata
+        .first()
+        .is_some_and(|token| !token.quoted && token.text == "\\#")
+    {
+        return Err("expected rdata in the \\# form".into());
+    }
+    let len: u16 = rdata
+        .get(1)
+        .and_then(|token| token.text.parse().ok())
+        .ok_or("invalid rdata length")?;
+
+    let hex: String = rdata[2..].iter().map(|token| token.text.as_str()).collect();
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("invalid hex in rdata".into());
+    }
+    if hex.len() != len as usize * 2 {
+        return Err("rdata length doesn't match the data".into());
+    }
+
+    // Only ASCII is left, so slicing by bytes can't split a character
+    (0..len as usize)
+        .map(|i| {
+            u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| "invalid hex in rdata".into())
+        })
+        .collect()
+}
+
+// Split a zone file into entries. Comments run from a semicolon to the end of
+// the line, and parentheses allow an entry to continue over several lines.
+// Within quotes, blanks and semicolons are part of the string. A backslash
+// escapes the next character or gives a byte as three digits (`\DDD`), and an
+// escaped character never ends a word. Escapes are kept as written for the
+// parser, since `\@` in a name differs from `@`.
+fn tokenize(text: &str) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut entry: Option<Entry> = None;
+    let mut depth = 0;
+
+    for (idx, line) in text.lines().enumerate() {
+        let number = idx + 1;
+        let mut chars = line.chars().peekable();
+
+        if depth == 0 {
+            if let Some(entry) = entry.take().filter(|entry| !entry.tokens.is_empty()) {
+                entries.push(entry);
+            }
+            entry = Some(Entry {
+                line: number,
+                leading_blank: line.starts_with([' ', '\t']),
+                tokens: Vec::new(),
+            });
+        }
+        let tokens = &mut entry.as_mut().unwrap().tokens;
+
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                ' ' | '\t' => continue,
+                '(' => depth += 1,
+                ')' => {
+                    if depth == 0 {
+                        return Err(format!("{}: unbalanced parenthesis", number).into());
+                    }
+                    depth -= 1;
+                }
+                '"' => {
+                    let mut text = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => text.push_str(&read_escape(&mut chars, number)?),
+                            Some(c) => text.push(c),
+                            None => return Err(format!("{}: unterminated string", number).into()),
+                        }
+                    }
+                    tokens.push(Token { text, quoted: true });
+                }
+                _ => {
+                    let mut text = String::new();
+                    let mut next = Some(c);
+                    while let Some(c) = next {
+                        if c == '\\' {
+                            text.push_str(&read_escape(&mut chars, number)?);
+                        } else {
+                            text.push(c);
+                        }
+                        next = chars.next_if(|c| !matches!(c, ' ' | '\t' | ';' | '(' | ')' | '"'));
+                    }
+                    tokens.push(Token {
+                        text,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+    }
+
+    if depth > 0 {
+        return Err("unbalanced parenthesis at end of file".into());
+    }
+    if let Some(entry) = entry.filter(|entry| !entry.tokens.is_empty()) {
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
+
+// Check the escape that follows a backslash, and return it as written
+fn read_escape(chars: &mut std::iter::Peekable<std::str::Chars>, line: usize) -> Result<String> {
+    let c = chars
+        .next()
+        .ok_or_else(|| format!("{}: dangling escape", line))?;
+    let mut escape = format!("\\{}", c);
+    if !c.is_ascii_digit() {
+        return Ok(escape);
+    }
+
+    for _ in 0..2 {
+        match chars.next_if(char::is_ascii_digit) {
+            Some(digit) => escape.push(digit),
+            None => return Err(format!("{}: invalid \\DDD escape", line).into()),
+        }
+    }
+    if escape[1..].parse::<u32>().unwrap_or(u32::MAX) > 255 {
+        return Err(format!("{}: invalid \\DDD escape", line).into());
+    }
+    Ok(escape)
+}
+
+// Undo the escapes the tokenizer kept in a word, which it has already
+// checked. `\DDD` gives a single byte, as in `escape_name`.
+fn unescape(word: &str) -> Vec<u8> {
+    let mut bytes = word.bytes();
+    let mut unescaped = Vec::new();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(d) if d.is_ascii_digit() => {
+                let code = bytes
+                    .by_ref()
+                    .take(2)
+                    .fold((d - b'0') as u32, |code, d| code * 10 + (d - b'0') as u32);
+                unescaped.push(code as u8);
+            }
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+// Whether a name holds a dot that is escaped, as `\.` or `\046`
+fn has_escaped_dot(name: &str) -> bool {
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let rest = chars.as_str();
+            if rest.starts_with('.') || rest.starts_with("046") {
+                return true;
+            }
+            chars.next();
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const ZONE: &str = r#"
+$TTL 1h
+$ORIGIN example.com.
+@       IN  SOA ns1 hostmaster (
+                2024010101 ; serial
+                7200       ; refresh
+                3600       ; retry
+                1209600    ; expire
+                300 )      ; minimum
+        IN  NS  ns1
+        IN  MX  10 mail.example.com.
+ns1     300 IN A 10.0.0.1
+www         A  10.0.0.2
+            AAAA 2001:db8::2
+txt         TXT "v=spf1 -all" "a \"quoted\"; string"
+$ORIGIN sub.example.com.
+host        CNAME www.example.com.
+"#;
+
+    #[test]
+    fn test_parse_zone() {
+        let records = parse_zone(ZONE, "example.com").unwrap();
+        assert_eq!(records.len(), 8);
+
+        match records[0] {
+            DnsRecord::SOA {
+                ref domain,
+                ref mname,
+                serial,
+                minimum,
+                ttl,
+                ..
+            } => {
+                assert_eq!(domain, "example.com");
+                assert_eq!(mname, "ns1.example.com");
+                assert_eq!(serial, 2024010101);
+                assert_eq!(minimum, 300);
+                assert_eq!(ttl, 3600);
+            }
+            _ => panic!("expected SOA, got {:?}", records[0]),
+        }
+
+        // Records without an owner belong to the previous one
+        assert_eq!(records[1].domain(), "example.com");
+        assert_eq!(records[3].ttl(), 300);
+        assert_eq!(records[5].domain(), "www.example.com");
+        assert_eq!(
+            records[6],
+            DnsRecord::TXT {
+                domain: "txt.example.com".to_string(),
+                data: vec![b"v=spf1 -all".to_vec(), b"a \"quoted\"; string".to_vec()],
+                ttl: 3600,
+            }
+        );
+        assert_eq!(records[7].domain(), "host.sub.example.com");
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let dir = env::temp_dir().join(format!("dns-zone-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hosts.zone"), "www A 10.0.0.2\n").unwrap();
+        fs::write(
+            dir.join("main.zone"),
+            "$TTL 300\n$INCLUDE hosts.zone internal.example.com.\nmail A 10.0.0.3\n",
+        )
+        .unwrap();
+
+        let records = parse_zone_file(dir.join("main.zone"), "example.com").unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // The origin given to the include only applies inside of it
+        assert_eq!(records[0].domain(), "www.internal.example.com");
+        assert_eq!(records[1].domain(), "mail.example.com");
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let err = parse_zone("$TTL 300\nwww A 10.0.0.300\n", "example.com").unwrap_err();
+        assert!(err.to_string().starts_with("<zone>:2:"), "{}", err);
+        assert!(parse_zone("www A 10.0.0.1\n", "example.com").is_err());
+        assert!(parse_zone("$TTL 300\nwww A ( 10.0.0.1\n", "example.com").is_err());
+
+        // Labels can't hold dots, so escaped ones are refused rather than
+        // read as separators
+        for text in ["$TTL 300\na\\.b A 10.0.0.1", "$TTL 300\nwww CNAME a\\046b"] {
+            let err = parse_zone(text, "example.com").unwrap_err();
+            assert!(err.to_string().starts_with("<zone>:2:"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_parse_generic_rdata() {
+        let records = parse_zone("x 300 TYPE65534 \\# 4 0A000001", "example.com").unwrap();
+        assert_eq!(records[0].generic_rdata().unwrap(), "\\# 4 0a000001");
+
+        // Bad data is an error rather than a panic
+        for text in [
+            "x TYPE65534 \\# 3 €€",
+            "x TYPE65534 \\# 1 0g",
+            "x TYPE65534 \\# 99999 00",
+        ] {
+            assert!(parse_zone(text, "example.com").is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_write_zone_round_trip() {
+        let mut records = parse_zone(ZONE, "example.com").unwrap();
+
+        // Labels with characters that have a meaning in zone files
+        let labels = [
+            "a b",
+            "a;b",
+            "a(b)",
+            "@",
+            "$x",
+            "back\\slash",
+            "quote\"",
+            "tab\t",
+        ];
+        for label in labels {
+            records.push(DnsRecord::CNAME {
+                domain: format!("{}.example.com", label),
+                host: format!("{}.example.org", label),
+                ttl: 300,
+            });
+        }
+        // And strings that aren't text at all
+        records.push(DnsRecord::TXT {
+            domain: "binary.example.com".to_string(),
+            data: vec![
+                vec![0x80, 0xff, 0x00, b'"', b'\\'],
+                b"\xc3\xa9t\xc3\xa9".to_vec(),
+            ],
+            ttl: 300,
+        });
+
+        let mut text = Vec::new();
+        write_zone(&mut text, "example.com", &records).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("$ORIGIN example.com.\nexample.com.\t3600\tIN\tSOA\t"));
+
+        let mut parsed = parse_zone(&text, "example.com").unwrap();
+        parsed.sort();
+        records.sort();
+        assert_eq!(parsed, records);
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl("300"), Some(300));
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("10x"), None);
+        assert_eq!(parse_ttl("1h30"), None);
+    }
+}